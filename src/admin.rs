@@ -1,12 +1,19 @@
 use crate::apikey::ApiKey;
-use crate::backend::MySqlBackend;
+use crate::backend::{MySqlBackend, Value};
 use crate::config::Config;
-use crate::questions::{LectureQuestion, LectureQuestionsContext};
+use crate::grading;
+use crate::groups;
+use crate::notifications;
+use crate::qtypes::{self, QuestionType};
+use crate::questions::{self, LectureId, LectureQuestionsContext, QuestionId};
+use crate::rubric;
+use chrono::naive::NaiveDateTime;
 use mysql::from_value;
 use rocket::form::Form;
 use rocket::http::Status;
 use rocket::outcome::IntoOutcome;
 use rocket::request::{self, FromRequest, Request};
+use rocket::response::status::BadRequest;
 use rocket::response::Redirect;
 use rocket::State;
 use rocket_dyn_templates::Template;
@@ -15,6 +22,27 @@ use std::sync::{Arc, Mutex};
 
 pub(crate) struct Admin;
 
+/// Tables holding per-question data, keyed by `lec` and `q`.
+const QUESTION_TABLES: [&str; 8] = [
+    "questions",
+    "answers",
+    "grades",
+    "rubric_items",
+    "rubric_applied",
+    "uploads",
+    "peer_reviews",
+    "regrades",
+];
+
+/// Tables holding other per-lecture data, keyed by `lec`. The `deanonymizations` audit log is
+/// deliberately not listed: it outlives the lecture and keeps the number it had at the time.
+const LECTURE_TABLES: [&str; 4] = [
+    "peer_assignments",
+    "lec_groups",
+    "lec_notifications",
+    "notification_digest",
+];
+
 #[derive(Debug)]
 pub(crate) enum AdminError {
     Unauthorized,
@@ -42,11 +70,134 @@ impl<'r> FromRequest<'r> for Admin {
 pub(crate) struct AddLectureQuestionForm {
     q_id: u64,
     q_prompt: String,
+    q_type: String,
+    q_options: String,
+    q_tolerance: Option<f64>,
+    q_answer_key: String,
+    q_points: Option<f64>,
+}
+
+impl AddLectureQuestionForm {
+    /// A question as if entered through the form, e.g., when importing questions from a file.
+    pub(crate) fn new(
+        q_id: u64,
+        q_prompt: String,
+        q_type: String,
+        q_options: String,
+        q_tolerance: Option<f64>,
+        q_answer_key: String,
+        q_points: Option<f64>,
+    ) -> Self {
+        AddLectureQuestionForm {
+            q_id: q_id,
+            q_prompt: q_prompt,
+            q_type: q_type,
+            q_options: q_options,
+            q_tolerance: q_tolerance,
+            q_answer_key: q_answer_key,
+            q_points: q_points,
+        }
+    }
+
+    /// The question's row in the `questions` table.
+    pub(crate) fn row(&self, lec: LectureId, position: u64) -> Vec<Value> {
+        vec![
+            lec.into(),
+            self.q_id.into(),
+            self.q_prompt.to_string().into(),
+            self.qtype().as_str().into(),
+            self.options().into(),
+            self.tolerance().into(),
+            self.answer_key().into(),
+            self.points().into(),
+            position.into(),
+        ]
+    }
+
+    /// Question type, falling back to free text for unknown types.
+    fn qtype(&self) -> QuestionType {
+        QuestionType::from_name(&self.q_type).unwrap_or(QuestionType::Text)
+    }
+
+    /// Options, one per line; only kept for multiple-choice (the choices) and file upload (the
+    /// accepted file extensions) questions.
+    fn options(&self) -> Option<String> {
+        if self.qtype().has_options() {
+            Some(qtypes::parse_options(&self.q_options).join("\n"))
+        } else {
+            None
+        }
+    }
+
+    /// Answer key for automatic grading; only kept for multiple-choice and numeric questions.
+    /// Multiple-choice keys list the correct options, one per line.
+    fn answer_key(&self) -> Option<String> {
+        let qtype = self.qtype();
+        if qtype.is_choice() {
+            let key = qtypes::parse_options(&self.q_answer_key);
+            if key.is_empty() {
+                None
+            } else {
+                Some(key.join("\n"))
+            }
+        } else if qtype == QuestionType::Numeric && !self.q_answer_key.trim().is_empty() {
+            Some(self.q_answer_key.trim().to_string())
+        } else {
+            None
+        }
+    }
+
+    /// Points the question is worth.
+    fn points(&self) -> f64 {
+        self.q_points.unwrap_or(1.0)
+    }
+
+    /// Numeric tolerance; only kept for numeric questions.
+    fn tolerance(&self) -> Option<f64> {
+        if self.qtype() == QuestionType::Numeric {
+            self.q_tolerance
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, FromForm)]
+pub(crate) struct ReleaseGradesForm {
+    released: bool,
+}
+
+#[derive(Debug, FromForm)]
+pub(crate) struct BlindGradingForm {
+    blind: bool,
+}
+
+#[derive(Debug, FromForm)]
+pub(crate) struct DeadlineForm {
+    deadline: String,
+}
+
+#[derive(Debug, FromForm)]
+pub(crate) struct RenameLectureForm {
+    lec_id: LectureId,
+    lec_label: String,
+}
+
+#[derive(Debug, FromForm)]
+pub(crate) struct ReorderForm {
+    /// Question IDs in their new order, separated by commas
+    order: String,
+}
+
+#[derive(Debug, FromForm)]
+pub(crate) struct DeleteForm {
+    /// Must be set if there are answers, which are deleted too
+    delete_answers: bool,
 }
 
 #[derive(Debug, FromForm)]
 pub(crate) struct AdminLecAdd {
-    lec_id: u8,
+    lec_id: LectureId,
     lec_label: String,
 }
 
@@ -57,6 +208,59 @@ pub(crate) struct User {
     is_admin: u8,
 }
 
+#[derive(Serialize)]
+struct LectureQuestionEditContext {
+    lec_id: LectureId,
+    question: Option<questions::LectureQuestion>,
+    rubric: Vec<rubric::RubricItem>,
+    parent: &'static str,
+}
+
+#[derive(Serialize)]
+struct DeleteContext {
+    lec_id: LectureId,
+    /// What is being deleted, e.g., "lecture 3"
+    what: String,
+    action: String,
+    num_answers: u64,
+    num_grades: u64,
+    num_uploads: u64,
+    parent: &'static str,
+}
+
+#[derive(Serialize)]
+struct StudentAnswer {
+    q: u64,
+    submitted_at: Option<NaiveDateTime>,
+    late: bool,
+    grade: Option<f64>,
+    /// Whether the answer is non-blank
+    answered: bool,
+}
+
+#[derive(Serialize)]
+struct StudentLecture {
+    id: u64,
+    label: String,
+    num_qs: u64,
+    num_answered: u64,
+    deadline: Option<NaiveDateTime>,
+    last_submitted: Option<NaiveDateTime>,
+    status: &'static str,
+    grade: Option<f64>,
+    max_score: f64,
+    /// Grades are hidden while the lecture is graded blind
+    blind: bool,
+    answers: Vec<StudentAnswer>,
+}
+
+#[derive(Serialize)]
+struct StudentContext {
+    email: String,
+    lectures: Vec<StudentLecture>,
+    parent: &'static str,
+}
+
 #[derive(Serialize)]
 struct UserContext {
     users: Vec<User>,
@@ -70,50 +274,80 @@ pub(crate) fn lec_add(_adm: Admin) -> Template {
     Template::render("admin/lecadd", &ctx)
 }
 
+/// The position after the last question of a lecture.
+fn next_position(bg: &mut MySqlBackend, num: LectureId) -> u64 {
+    let res = bg.prep_exec(
+        "SELECT MAX(COALESCE(position, q)) FROM questions WHERE lec = ?",
+        vec![num.into()],
+    );
+    match res.get(0) {
+        Some(r) if r[0] != Value::NULL => from_value::<u64>(r[0].clone()) + 1,
+        _ => 1,
+    }
+}
+
 #[post("/", data = "<data>")]
 pub(crate) fn lec_add_submit(
     _adm: Admin,
     data: Form<AdminLecAdd>,
     backend: &State<Arc<Mutex<MySqlBackend>>>,
-) -> Redirect {
+) -> Result<Redirect, BadRequest<String>> {
     // insert into MySql if not exists
     let mut bg = backend.lock().unwrap();
-    bg.insert(
-        "lectures",
-        vec![
-            (data.lec_id as u64).into(),
-            data.lec_label.to_string().into(),
-        ],
-    );
+    bg.transaction(|bg| {
+        bg.insert(
+            "lectures",
+            vec![
+                data.lec_id.into(),
+                data.lec_label.to_string().into(),
+                0.into(),
+                0.into(),
+                Value::NULL,
+            ],
+        )
+    })
+    .map_err(|e| {
+        BadRequest(Some(format!(
+            "Adding lecture {} failed: {}",
+            data.lec_id, e
+        )))
+    })?;
     drop(bg);
 
-    Redirect::to("/leclist")
+    Ok(Redirect::to("/leclist"))
 }
 
 #[get("/<num>")]
-pub(crate) fn lec(_adm: Admin, num: u8, backend: &State<Arc<Mutex<MySqlBackend>>>) -> Template {
+pub(crate) fn lec(
+    _adm: Admin,
+    num: LectureId,
+    backend: &State<Arc<Mutex<MySqlBackend>>>,
+) -> Template {
     let mut bg = backend.lock().unwrap();
-    let res = bg.prep_exec(
-        "SELECT * FROM questions WHERE lec = ?",
-        vec![(num as u64).into()],
-    );
+    let mut res = bg.prep_exec("SELECT * FROM questions WHERE lec = ?", vec![num.into()]);
+    let label = questions::lecture_label(&mut bg, num);
+    let released = grading::grades_released(&mut bg, num);
+    let blind = lec_blind_grading(&mut bg, num);
+    let deadline = questions::lecture_deadline(&mut bg, num);
+    let notifications = notifications::settings(&mut bg, num);
+    let groups = groups::lecture_groups(&mut bg, num);
     drop(bg);
-    let mut qs: Vec<_> = res
+    res.sort_by_key(|r| questions::question_order(r));
+    let qs: Vec<_> = res
         .into_iter()
-        .map(|r| {
-            let id: u64 = from_value(r[1].clone());
-            LectureQuestion {
-                id: id,
-                prompt: from_value(r[2].clone()),
-                answer: None,
-            }
-        })
+        .map(|r| questions::question_from_row(&r, None))
         .collect();
-    qs.sort_by(|a, b| a.id.cmp(&b.id));
 
     let ctx = LectureQuestionsContext {
         lec_id: num,
+        label: label,
         questions: qs,
+        grades_released: released,
+        blind_grading: blind,
+        deadline: deadline,
+        notifications: Some(notifications),
+        group: None,
+        groups: groups,
         parent: "layout",
     };
     Template::render("admin/lec", &ctx)
@@ -122,64 +356,73 @@ pub(crate) fn lec(_adm: Admin, num: u8, backend: &State<Arc<Mutex<MySqlBackend>>
 #[post("/<num>", data = "<data>")]
 pub(crate) fn addq(
     _adm: Admin,
-    num: u8,
+    num: LectureId,
     data: Form<AddLectureQuestionForm>,
     backend: &State<Arc<Mutex<MySqlBackend>>>,
-) -> Redirect {
+) -> Result<Redirect, BadRequest<String>> {
     let mut bg = backend.lock().unwrap();
-    bg.insert(
-        "questions",
-        vec![
-            (num as u64).into(),
-            (data.q_id as u64).into(),
-            data.q_prompt.to_string().into(),
-        ],
-    );
+    // new questions go last
+    let position = next_position(&mut bg, num);
+    // in a transaction, so that a failing insert (e.g., a duplicate question number) is
+    // reported rather than retried
+    bg.transaction(|bg| bg.insert("questions", data.row(num, position)))
+        .map_err(|e| {
+            BadRequest(Some(format!(
+                "Adding question {} to lecture {} failed: {}",
+                data.q_id, num, e
+            )))
+        })?;
     drop(bg);
 
-    Redirect::to(format!("/admin/lec/{}", num))
+    Ok(Redirect::to(format!("/admin/lec/{}", num)))
 }
 
 #[get("/<num>/<qnum>")]
 pub(crate) fn editq(
     _adm: Admin,
-    num: u8,
-    qnum: u8,
+    num: LectureId,
+    qnum: QuestionId,
     backend: &State<Arc<Mutex<MySqlBackend>>>,
 ) -> Template {
     let mut bg = backend.lock().unwrap();
-    let res = bg.prep_exec(
-        "SELECT * FROM questions WHERE lec = ?",
-        vec![(num as u64).into()],
-    );
+    let res = bg.prep_exec("SELECT * FROM questions WHERE lec = ?", vec![num.into()]);
+    let rubric = rubric::items(&mut bg, num, qnum);
     drop(bg);
 
-    let mut ctx = HashMap::new();
-    for r in res {
-        if r[1] == (qnum as u64).into() {
-            ctx.insert("lec_qprompt", from_value(r[2].clone()));
-        }
-    }
-    ctx.insert("lec_id", format!("{}", num));
-    ctx.insert("lec_qnum", format!("{}", qnum));
-    ctx.insert("parent", String::from("layout"));
+    let question = res
+        .into_iter()
+        .find(|r| r[1] == qnum.into())
+        .map(|r| questions::question_from_row(&r, None));
+
+    let ctx = LectureQuestionEditContext {
+        lec_id: num,
+        question: question,
+        rubric: rubric,
+        parent: "layout",
+    };
     Template::render("admin/lecedit", &ctx)
 }
 
 #[post("/editq/<num>", data = "<data>")]
 pub(crate) fn editq_submit(
     _adm: Admin,
-    num: u8,
+    num: LectureId,
     data: Form<AddLectureQuestionForm>,
     backend: &State<Arc<Mutex<MySqlBackend>>>,
 ) -> Redirect {
     let mut bg = backend.lock().unwrap();
     bg.prep_exec(
-        "UPDATE questions SET question = ? WHERE lec = ? AND q = ?",
+        "UPDATE questions SET question = ?, qtype = ?, options = ?, tolerance = ?, \
+         answer_key = ?, points = ? WHERE lec = ? AND q = ?",
         vec![
             data.q_prompt.to_string().into(),
-            (num as u64).into(),
-            (data.q_id as u64).into(),
+            data.qtype().as_str().into(),
+            data.options().into(),
+            data.tolerance().into(),
+            data.answer_key().into(),
+            data.points().into(),
+            num.into(),
+            data.q_id.into(),
         ],
     );
     drop(bg);
@@ -187,6 +430,327 @@ pub(crate) fn editq_submit(
     Redirect::to(format!("/admin/lec/{}", num))
 }
 
+#[post("/release/<num>", data = "<data>")]
+pub(crate) fn release_grades(
+    _adm: Admin,
+    num: LectureId,
+    data: Form<ReleaseGradesForm>,
+    backend: &State<Arc<Mutex<MySqlBackend>>>,
+) -> Redirect {
+    let mut bg = backend.lock().unwrap();
+    bg.prep_exec(
+        "UPDATE lectures SET grades_released = ? WHERE id = ?",
+        vec![(data.released as u64).into(), num.into()],
+    );
+    drop(bg);
+
+    Redirect::to(format!("/admin/lec/{}", num))
+}
+
+/// Whether blind grading is enabled for a lecture (regardless of whether grades are released).
+fn lec_blind_grading(bg: &mut MySqlBackend, num: LectureId) -> bool {
+    let res = bg.prep_exec(
+        "SELECT blind_grading FROM lectures WHERE id = ?",
+        vec![num.into()],
+    );
+    match res.get(0) {
+        Some(r) if r[0] != Value::NULL => from_value::<u64>(r[0].clone()) != 0,
+        _ => false,
+    }
+}
+
+#[post("/blind/<num>", data = "<data>")]
+pub(crate) fn blind_grading(
+    _adm: Admin,
+    apikey: ApiKey,
+    num: LectureId,
+    data: Form<BlindGradingForm>,
+    backend: &State<Arc<Mutex<MySqlBackend>>>,
+    config: &State<Config>,
+) -> Redirect {
+    let mut bg = backend.lock().unwrap();
+    // turning blind grading off before grades are released reveals every student at once,
+    // so log it like de-anonymizing each of them
+    if !data.blind && grading::is_blind(&mut bg, num) {
+        for s in grading::students(&mut bg, config, num) {
+            grading::log_deanonymization(&mut bg, &apikey.user, num, &s.display, &s.email);
+        }
+    }
+    bg.prep_exec(
+        "UPDATE lectures SET blind_grading = ? WHERE id = ?",
+        vec![(data.blind as u64).into(), num.into()],
+    );
+    drop(bg);
+
+    Redirect::to(format!("/admin/lec/{}", num))
+}
+
+#[post("/deadline/<num>", data = "<data>")]
+pub(crate) fn set_deadline(
+    _adm: Admin,
+    num: LectureId,
+    data: Form<DeadlineForm>,
+    backend: &State<Arc<Mutex<MySqlBackend>>>,
+) -> Result<Redirect, BadRequest<String>> {
+    // browsers submit `datetime-local` inputs with or without seconds; empty clears the deadline
+    let deadline = match data.deadline.trim() {
+        "" => Value::NULL,
+        d => NaiveDateTime::parse_from_str(d, "%Y-%m-%dT%H:%M")
+            .or_else(|_| NaiveDateTime::parse_from_str(d, "%Y-%m-%dT%H:%M:%S"))
+            .map_err(|_| BadRequest(Some(format!("Invalid deadline: {}", d))))?
+            .into(),
+    };
+    let mut bg = backend.lock().unwrap();
+    bg.prep_exec(
+        "UPDATE lectures SET deadline = ? WHERE id = ?",
+        vec![deadline, num.into()],
+    );
+    drop(bg);
+
+    Ok(Redirect::to(format!("/admin/lec/{}", num)))
+}
+
+#[post("/rename/<num>", data = "<data>")]
+pub(crate) fn rename(
+    _adm: Admin,
+    num: LectureId,
+    data: Form<RenameLectureForm>,
+    backend: &State<Arc<Mutex<MySqlBackend>>>,
+) -> Result<Redirect, BadRequest<String>> {
+    let mut bg = backend.lock().unwrap();
+    if data.lec_id != num {
+        let taken = !bg
+            .prep_exec(
+                "SELECT id FROM lectures WHERE id = ?",
+                vec![data.lec_id.into()],
+            )
+            .is_empty();
+        if taken {
+            return Err(BadRequest(Some(format!(
+                "Lecture {} already exists",
+                data.lec_id
+            ))));
+        }
+    }
+
+    bg.transaction(|bg| {
+        bg.prep_exec(
+            "UPDATE lectures SET id = ?, label = ? WHERE id = ?",
+            vec![
+                data.lec_id.into(),
+                data.lec_label.to_string().into(),
+                num.into(),
+            ],
+        );
+        // renumbering moves everything that belongs to the lecture along with it
+        if data.lec_id != num {
+            for table in QUESTION_TABLES.iter().chain(LECTURE_TABLES.iter()) {
+                bg.prep_exec(
+                    &format!("UPDATE {} SET lec = ? WHERE lec = ?", table),
+                    vec![data.lec_id.into(), num.into()],
+                );
+            }
+        }
+    })
+    .map_err(|e| BadRequest(Some(format!("Renaming lecture {} failed: {}", num, e))))?;
+    drop(bg);
+
+    Ok(Redirect::to(format!("/admin/lec/{}", data.lec_id)))
+}
+
+#[post("/reorder/<num>", data = "<data>")]
+pub(crate) fn reorder(
+    _adm: Admin,
+    num: LectureId,
+    data: Form<ReorderForm>,
+    backend: &State<Arc<Mutex<MySqlBackend>>>,
+) -> Result<Redirect, BadRequest<String>> {
+    let order = data
+        .order
+        .split(',')
+        .map(|id| id.trim().parse::<u64>())
+        .collect::<Result<Vec<u64>, _>>()
+        .map_err(|_| BadRequest(Some(format!("Invalid question order: {}", data.order))))?;
+
+    let mut bg = backend.lock().unwrap();
+    let mut current: Vec<u64> = bg
+        .prep_exec("SELECT q FROM questions WHERE lec = ?", vec![num.into()])
+        .into_iter()
+        .map(|r| from_value(r[0].clone()))
+        .collect();
+    current.sort();
+    let mut given = order.clone();
+    given.sort();
+    if given != current {
+        return Err(BadRequest(Some(String::from(
+            "The new order must list every question of the lecture exactly once",
+        ))));
+    }
+
+    bg.transaction(|bg| {
+        for (i, q) in order.iter().enumerate() {
+            bg.prep_exec(
+                "UPDATE questions SET position = ? WHERE lec = ? AND q = ?",
+                vec![(i as u64 + 1).into(), num.into(), (*q).into()],
+            );
+        }
+    })
+    .map_err(|e| {
+        BadRequest(Some(format!(
+            "Reordering the questions of lecture {} failed: {}",
+            num, e
+        )))
+    })?;
+    drop(bg);
+
+    Ok(Redirect::to(format!("/admin/lec/{}", num)))
+}
+
+/// Counts the answers, grades and uploads that deleting a lecture (or one of its questions)
+/// would delete.
+fn count_answers(
+    bg: &mut MySqlBackend,
+    num: LectureId,
+    qnum: Option<QuestionId>,
+) -> (u64, u64, u64) {
+    let (filter, params): (&str, Vec<Value>) = match qnum {
+        Some(q) => ("WHERE lec = ? AND q = ?", vec![num.into(), q.into()]),
+        None => ("WHERE lec = ?", vec![num.into()]),
+    };
+    let mut count = |table: &str| -> u64 {
+        let res = bg.prep_exec(
+            &format!("SELECT COUNT(*) FROM {} {}", table, filter),
+            params.clone(),
+        );
+        from_value(res[0][0].clone())
+    };
+    (count("answers"), count("grades"), count("uploads"))
+}
+
+fn delete_context(
+    bg: &mut MySqlBackend,
+    num: LectureId,
+    qnum: Option<QuestionId>,
+) -> DeleteContext {
+    let (num_answers, num_grades, num_uploads) = count_answers(bg, num, qnum);
+    let (what, action) = match qnum {
+        Some(q) => (
+            format!("question {} of lecture {}", q, num),
+            format!("/admin/lec/delete/{}/{}", num, q),
+        ),
+        None => (
+            format!("lecture {}", num),
+            format!("/admin/lec/delete/{}", num),
+        ),
+    };
+    DeleteContext {
+        lec_id: num,
+        what: what,
+        action: action,
+        num_answers: num_answers,
+        num_grades: num_grades,
+        num_uploads: num_uploads,
+        parent: "layout",
+    }
+}
+
+#[get("/delete/<num>")]
+pub(crate) fn delete_lec(
+    _adm: Admin,
+    num: LectureId,
+    backend: &State<Arc<Mutex<MySqlBackend>>>,
+) -> Template {
+    let mut bg = backend.lock().unwrap();
+    let ctx = delete_context(&mut bg, num, None);
+    drop(bg);
+    Template::render("admin/delete", &ctx)
+}
+
+#[post("/delete/<num>", data = "<data>")]
+pub(crate) fn delete_lec_submit(
+    _adm: Admin,
+    num: LectureId,
+    data: Form<DeleteForm>,
+    backend: &State<Arc<Mutex<MySqlBackend>>>,
+) -> Result<Redirect, BadRequest<String>> {
+    let mut bg = backend.lock().unwrap();
+    let (num_answers, _, _) = count_answers(&mut bg, num, None);
+    if num_answers > 0 && !data.delete_answers {
+        return Err(BadRequest(Some(format!(
+            "Lecture {} has {} answers; confirm that they should be deleted",
+            num, num_answers
+        ))));
+    }
+
+    bg.transaction(|bg| {
+        let vnum: Value = num.into();
+        bg.prep_exec(
+            "DELETE FROM group_members WHERE group_id IN (SELECT id FROM lec_groups WHERE lec = ?)",
+            vec![vnum.clone()],
+        );
+        for table in QUESTION_TABLES.iter().chain(LECTURE_TABLES.iter()) {
+            bg.prep_exec(
+                &format!("DELETE FROM {} WHERE lec = ?", table),
+                vec![vnum.clone()],
+            );
+        }
+        bg.prep_exec("DELETE FROM lectures WHERE id = ?", vec![vnum]);
+    })
+    .map_err(|e| BadRequest(Some(format!("Deleting lecture {} failed: {}", num, e))))?;
+    drop(bg);
+
+    Ok(Redirect::to("/leclist"))
+}
+
+#[get("/delete/<num>/<qnum>")]
+pub(crate) fn delete_question(
+    _adm: Admin,
+    num: LectureId,
+    qnum: QuestionId,
+    backend: &State<Arc<Mutex<MySqlBackend>>>,
+) -> Template {
+    let mut bg = backend.lock().unwrap();
+    let ctx = delete_context(&mut bg, num, Some(qnum));
+    drop(bg);
+    Template::render("admin/delete", &ctx)
+}
+
+#[post("/delete/<num>/<qnum>", data = "<data>")]
+pub(crate) fn delete_question_submit(
+    _adm: Admin,
+    num: LectureId,
+    qnum: QuestionId,
+    data: Form<DeleteForm>,
+    backend: &State<Arc<Mutex<MySqlBackend>>>,
+) -> Result<Redirect, BadRequest<String>> {
+    let mut bg = backend.lock().unwrap();
+    let (num_answers, _, _) = count_answers(&mut bg, num, Some(qnum));
+    if num_answers > 0 && !data.delete_answers {
+        return Err(BadRequest(Some(format!(
+            "Question {} has {} answers; confirm that they should be deleted",
+            qnum, num_answers
+        ))));
+    }
+
+    bg.transaction(|bg| {
+        for table in QUESTION_TABLES.iter() {
+            bg.prep_exec(
+                &format!("DELETE FROM {} WHERE lec = ? AND q = ?", table),
+                vec![num.into(), qnum.into()],
+            );
+        }
+    })
+    .map_err(|e| {
+        BadRequest(Some(format!(
+            "Deleting question {} of lecture {} failed: {}",
+            qnum, num, e
+        )))
+    })?;
+    drop(bg);
+
+    Ok(Redirect::to(format!("/admin/lec/{}", num)))
+}
+
 #[get("/")]
 pub(crate) fn get_registered_users(
     _adm: Admin,
@@ -216,3 +780,126 @@ pub(crate) fn get_registered_users(
     };
     Template::render("admin/users", &ctx)
 }
+
+#[get("/<email>")]
+pub(crate) fn user_progress(
+    _adm: Admin,
+    email: String,
+    backend: &State<Arc<Mutex<MySqlBackend>>>,
+) -> Template {
+    let mut bg = backend.lock().unwrap();
+    let lres = bg.prep_exec(
+        "SELECT lectures.id, lectures.label, lectures.deadline, lec_qcount.qcount \
+         FROM lectures LEFT JOIN lec_qcount ON (lectures.id = lec_qcount.lec) ORDER BY lectures.id",
+        vec![],
+    );
+    let pres = bg.prep_exec(
+        "SELECT lec, SUM(COALESCE(points, 1)) FROM questions GROUP BY lec",
+        vec![],
+    );
+    let ares = bg.prep_exec(
+        "SELECT lec, q, submitted_at, score, answer <> '' FROM answers WHERE email = ? \
+         ORDER BY lec, q",
+        vec![email.clone().into()],
+    );
+    let gres = bg.prep_exec(
+        "SELECT lec, q, score FROM grades WHERE email = ?",
+        vec![email.clone().into()],
+    );
+    // grades of blind lectures would tie the student to their pseudonym
+    let blind: Vec<u64> = lres
+        .iter()
+        .map(|r| from_value(r[0].clone()))
+        .filter(|id| grading::is_blind(&mut bg, *id))
+        .collect();
+    drop(bg);
+
+    let max_scores: HashMap<u64, f64> = pres
+        .into_iter()
+        .map(|r| (from_value(r[0].clone()), from_value(r[1].clone())))
+        .collect();
+    let mut grades = HashMap::new();
+    for r in gres {
+        if r[2] != Value::NULL {
+            let key: (u64, u64) = (from_value(r[0].clone()), from_value(r[1].clone()));
+            grades.insert(key, from_value::<f64>(r[2].clone()));
+        }
+    }
+    let mut answers: HashMap<u64, Vec<_>> = HashMap::new();
+    for r in ares {
+        let lec: u64 = from_value(r[0].clone());
+        let q: u64 = from_value(r[1].clone());
+        let auto_score: Option<f64> = if r[3] == Value::NULL {
+            None
+        } else {
+            Some(from_value(r[3].clone()))
+        };
+        answers.entry(lec).or_default().push((
+            q,
+            from_value::<u64>(r[4].clone()) != 0,
+            questions::opt_datetime(&r[2]),
+            if blind.contains(&lec) {
+                None
+            } else {
+                // a manual grade overrides the automatic score
+                grades.get(&(lec, q)).cloned().or(auto_score)
+            },
+        ));
+    }
+
+    let lectures = lres
+        .into_iter()
+        .map(|r| {
+            let id: u64 = from_value(r[0].clone());
+            let deadline = questions::opt_datetime(&r[2]);
+            let num_qs = if r[3] == Value::NULL {
+                0u64
+            } else {
+                from_value(r[3].clone())
+            };
+            let answers: Vec<_> = answers
+                .remove(&id)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(q, answered, submitted_at, grade)| StudentAnswer {
+                    q: q,
+                    submitted_at: submitted_at,
+                    late: match (submitted_at, deadline) {
+                        (Some(s), Some(d)) => s > d,
+                        _ => false,
+                    },
+                    grade: grade,
+                    answered: answered,
+                })
+                .collect();
+            // blank answers do not count towards progress
+            let num_answered = answers.iter().filter(|a| a.answered).count() as u64;
+            let last_submitted = answers.iter().filter_map(|a| a.submitted_at).max();
+            let grades: Vec<f64> = answers.iter().filter_map(|a| a.grade).collect();
+            StudentLecture {
+                id: id,
+                label: from_value(r[1].clone()),
+                num_qs: num_qs,
+                num_answered: num_answered,
+                deadline: deadline,
+                last_submitted: last_submitted,
+                status: questions::lecture_status(num_answered, num_qs, last_submitted, deadline),
+                grade: if grades.is_empty() {
+                    None
+                } else {
+                    Some(grades.iter().sum())
+                },
+                max_score: max_scores.get(&id).cloned().unwrap_or(0.0),
+                blind: blind.contains(&id),
+                answers: answers,
+            }
+        })
+        .collect();
+
+    let ctx = StudentContext {
+        email: email,
+        lectures: lectures,
+        parent: "layout",
+    };
+    Template::render("admin/user", &ctx)
+}
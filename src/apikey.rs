@@ -1,6 +1,6 @@
 use crate::backend::MySqlBackend;
 use crate::config::Config;
-use crate::email;
+use crate::outbox;
 use crypto::digest::Digest;
 use crypto::sha2::Sha256;
 use mysql::from_value;
@@ -59,6 +59,23 @@ impl<'r> FromRequest<'r> for ApiKey {
     }
 }
 
+/// Generates the API key for an email address.
+pub(crate) fn generate_key(config: &Config, email: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.input_str(email);
+    // add a secret to make API keys unforgeable without access to the server
+    hasher.input_str(&config.secret);
+    hasher.result_str()
+}
+
+/// Queues an email with a user's API key.
+pub(crate) fn send_key(bg: &mut MySqlBackend, config: &Config, email: &str, key: &str) {
+    let mut ctx = HashMap::new();
+    ctx.insert("class", config.class.as_str());
+    ctx.insert("apikey", key);
+    outbox::enqueue(bg, vec![email.to_string()], None, "apikey", &ctx);
+}
+
 #[post("/", data = "<data>")]
 pub(crate) fn generate(
     data: Form<ApiKeyRequest>,
@@ -66,11 +83,7 @@ pub(crate) fn generate(
     config: &State<Config>,
 ) -> Template {
     // generate an API key from email address
-    let mut hasher = Sha256::new();
-    hasher.input_str(&data.email);
-    // add a secret to make API keys unforgeable without access to the server
-    hasher.input_str(&config.secret);
-    let hash = hasher.result_str();
+    let hash = generate_key(config, &data.email);
 
     let is_admin = if config.admins.contains(&data.email) {
         1.into()
@@ -86,14 +99,7 @@ pub(crate) fn generate(
     );
 
     if config.send_emails {
-        email::send(
-            bg.log.clone(),
-            "no-reply@csci2390-submit.cs.brown.edu".into(),
-            vec![data.email.clone()],
-            format!("{} API key", config.class),
-            format!("Your {} API key is: {}\n", config.class, hash.as_str(),),
-        )
-        .expect("failed to send API key email");
+        send_key(&mut bg, config, &data.email, &hash);
     }
     drop(bg);
 
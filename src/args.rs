@@ -1,16 +1,40 @@
 use crate::config;
-use clap::{App, Arg};
+use clap::{App, Arg, SubCommand};
 
 #[cfg_attr(rustfmt, rustfmt_skip)]
 const WEBSUBMIT_USAGE: &'static str = "\
 EXAMPLES:
   websubmit -i csci2390
-  websubmit -i csci2390 -c csci2390-f19.toml";
+  websubmit -i csci2390 -c csci2390-f19.toml
+  websubmit -i csci2390 import --dry-run lecture3.toml
+  websubmit -i csci2390 roster --create-users --send-keys roster.csv
+  websubmit -i csci2390 clone-term csci2390_f20";
+
+#[derive(Clone, Debug)]
+pub enum Command {
+    /// Import a lecture and its questions from a file
+    Import {
+        file: String,
+        format: Option<String>,
+        dry_run: bool,
+    },
+    /// Import the course roster from a registrar CSV file
+    Roster {
+        file: String,
+        create_users: bool,
+        send_keys: bool,
+        dry_run: bool,
+    },
+    /// Copy all lectures (without answers) from another class deployment
+    CloneTerm { from: String, dry_run: bool },
+}
 
 #[derive(Clone, Debug)]
 pub struct Args {
     pub class: String,
     pub config: config::Config,
+    /// Command to run instead of the web server
+    pub command: Option<Command>,
 }
 
 pub fn parse_args() -> Args {
@@ -35,6 +59,68 @@ pub fn parse_args() -> Args {
                 .required(true)
                 .help("Short textual identifier for the class hosted (used as Noria deployment name)."),
         )
+        .subcommand(
+            SubCommand::with_name("import")
+                .about("Imports a lecture and its questions from a TOML, JSON or Markdown file.")
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["toml", "json", "md"])
+                        .help("File format (default: from the file extension)."),
+                )
+                .arg(
+                    Arg::with_name("dry-run")
+                        .long("dry-run")
+                        .help("Only show what would change."),
+                )
+                .arg(
+                    Arg::with_name("FILE")
+                        .required(true)
+                        .help("File to import."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("roster")
+                .about("Imports the course roster from a registrar CSV file with an email column.")
+                .arg(
+                    Arg::with_name("create-users")
+                        .long("create-users")
+                        .help("Create API keys for students who do not have one yet."),
+                )
+                .arg(
+                    Arg::with_name("send-keys")
+                        .long("send-keys")
+                        .requires("create-users")
+                        .help(
+                            "Email newly created API keys to students (sent by the running server).",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("dry-run")
+                        .long("dry-run")
+                        .help("Only show what would change."),
+                )
+                .arg(
+                    Arg::with_name("FILE")
+                        .required(true)
+                        .help("Roster CSV file."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("clone-term")
+                .about("Copies all lectures and their questions, but no answers, from another class.")
+                .arg(
+                    Arg::with_name("dry-run")
+                        .long("dry-run")
+                        .help("Only show what would be copied."),
+                )
+                .arg(
+                    Arg::with_name("FROM_CLASS_ID")
+                        .required(true)
+                        .help("Class ID of the deployment to copy from."),
+                ),
+        )
         .after_help(WEBSUBMIT_USAGE)
         .get_matches();
 
@@ -42,5 +128,23 @@ pub fn parse_args() -> Args {
         class: String::from(args.value_of("class").unwrap()),
         config: config::parse(args.value_of("config").expect("Failed to parse config!"))
             .expect("failed to parse config"),
+        command: match args.subcommand() {
+            ("import", Some(m)) => Some(Command::Import {
+                file: String::from(m.value_of("FILE").unwrap()),
+                format: m.value_of("format").map(String::from),
+                dry_run: m.is_present("dry-run"),
+            }),
+            ("roster", Some(m)) => Some(Command::Roster {
+                file: String::from(m.value_of("FILE").unwrap()),
+                create_users: m.is_present("create-users"),
+                send_keys: m.is_present("send-keys"),
+                dry_run: m.is_present("dry-run"),
+            }),
+            ("clone-term", Some(m)) => Some(Command::CloneTerm {
+                from: String::from(m.value_of("FROM_CLASS_ID").unwrap()),
+                dry_run: m.is_present("dry-run"),
+            }),
+            _ => None,
+        },
     }
 }
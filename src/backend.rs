@@ -12,6 +12,11 @@ pub struct MySqlBackend {
     db_user: String,
     db_password: String,
     db_name: String,
+    /// Whether a transaction is open. Failing statements then do not reconnect and retry,
+    /// since that would silently end the transaction.
+    in_transaction: bool,
+    /// The first error inside the open transaction; later statements are skipped.
+    tx_error: Option<mysql::Error>,
 }
 
 impl MySqlBackend {
@@ -73,6 +78,8 @@ impl MySqlBackend {
             db_user: String::from(user),
             db_password: String::from(password),
             db_name: String::from(dbname),
+            in_transaction: false,
+            tx_error: None,
         })
     }
 
@@ -88,6 +95,9 @@ impl MySqlBackend {
     }
 
     pub fn prep_exec(&mut self, sql: &str, params: Vec<Value>) -> Vec<Vec<Value>> {
+        if self.tx_error.is_some() {
+            return vec![];
+        }
         if !self.prep_stmts.contains_key(sql) {
             let stmt = self
                 .handle
@@ -100,6 +110,11 @@ impl MySqlBackend {
                 .handle
                 .exec_iter(self.prep_stmts[sql].clone(), params.clone())
             {
+                Err(e) if self.in_transaction => {
+                    warn!(self.log, "query \'{}\' failed in transaction ({})", sql, e);
+                    self.tx_error = Some(e);
+                    return vec![];
+                }
                 Err(e) => {
                     warn!(
                         self.log,
@@ -121,6 +136,37 @@ impl MySqlBackend {
         }
     }
 
+    /// Runs `f` inside a transaction. If any statement fails, the statements after it are
+    /// skipped, the transaction is rolled back and the error is returned. Inside another
+    /// transaction, `f` joins the open transaction, whose end reports any error.
+    pub fn transaction<T, F>(&mut self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Self) -> T,
+    {
+        if self.in_transaction {
+            return Ok(f(self));
+        }
+        self.handle.query_drop("START TRANSACTION")?;
+        self.in_transaction = true;
+        let res = f(self);
+        self.in_transaction = false;
+        match self.tx_error.take() {
+            None => {
+                debug!(self.log, "ending transaction with COMMIT");
+                self.handle.query_drop("COMMIT")?;
+                Ok(res)
+            }
+            Some(e) => {
+                debug!(self.log, "ending transaction with ROLLBACK");
+                if self.handle.query_drop("ROLLBACK").is_err() {
+                    // the server rolls back by itself when the connection is gone
+                    self.reconnect();
+                }
+                Err(e)
+            }
+        }
+    }
+
     fn do_insert(&mut self, table: &str, vals: Vec<Value>, replace: bool) {
         let op = if replace { "REPLACE" } else { "INSERT" };
         let q = format!(
@@ -130,6 +176,18 @@ impl MySqlBackend {
             vals.iter().map(|_| "?").collect::<Vec<&str>>().join(",")
         );
         debug!(self.log, "executed insert query {} for row {:?}", q, vals);
+        if self.in_transaction {
+            if self.tx_error.is_none() {
+                if let Err(e) = self.handle.exec_drop(q.clone(), vals) {
+                    warn!(
+                        self.log,
+                        "insert into {} failed in transaction ({})", table, e
+                    );
+                    self.tx_error = Some(e);
+                }
+            }
+            return;
+        }
         while let Err(e) = self.handle.exec_drop(q.clone(), vals.clone()) {
             warn!(
                 self.log,
@@ -0,0 +1,161 @@
+use crate::admin::Admin;
+use crate::backend::{MySqlBackend, Value};
+use crate::notifications;
+use crate::questions::LectureId;
+use mysql::from_value;
+use rocket::form::Form;
+use rocket::response::status::BadRequest;
+use rocket::response::Redirect;
+use rocket::State;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, FromForm)]
+pub(crate) struct CloneLectureForm {
+    lec_id: LectureId,
+    lec_label: String,
+}
+
+/// A lecture's settings, questions and rubric, without any student data.
+pub(crate) struct LectureCopy {
+    pub id: LectureId,
+    pub label: String,
+    blind_grading: Value,
+    notifications: notifications::Settings,
+    /// Question rows, without the lecture column
+    questions: Vec<Vec<Value>>,
+    /// Rubric item rows (q, points, description)
+    rubric: Vec<Vec<Value>>,
+}
+
+/// Reads a lecture for copying, or `None` if it does not exist.
+pub(crate) fn read_lecture(bg: &mut MySqlBackend, num: LectureId) -> Option<LectureCopy> {
+    let lres = bg.prep_exec(
+        "SELECT id, label, blind_grading FROM lectures WHERE id = ?",
+        vec![num.into()],
+    );
+    let l = lres.get(0)?;
+    let questions = bg.prep_exec(
+        "SELECT q, question, qtype, options, tolerance, answer_key, points, position \
+         FROM questions WHERE lec = ? ORDER BY q",
+        vec![num.into()],
+    );
+    let rubric = bg.prep_exec(
+        "SELECT q, points, description FROM rubric_items WHERE lec = ? ORDER BY id",
+        vec![num.into()],
+    );
+    Some(LectureCopy {
+        id: from_value(l[0].clone()),
+        label: from_value(l[1].clone()),
+        blind_grading: l[2].clone(),
+        notifications: notifications::settings(bg, num),
+        questions: questions,
+        rubric: rubric,
+    })
+}
+
+/// The IDs of all lectures, in order.
+pub(crate) fn lecture_ids(bg: &mut MySqlBackend) -> Vec<LectureId> {
+    bg.prep_exec("SELECT id FROM lectures ORDER BY id", vec![])
+        .into_iter()
+        .map(|r| from_value(r[0].clone()))
+        .collect()
+}
+
+fn lecture_exists(bg: &mut MySqlBackend, num: LectureId) -> bool {
+    !bg.prep_exec("SELECT id FROM lectures WHERE id = ?", vec![num.into()])
+        .is_empty()
+}
+
+/// Writes a copy of a lecture under a new ID and label. Grades start out hidden and the
+/// deadline unset, since both belong to the original's run of the lecture. Nothing is written if
+/// any row fails to insert.
+pub(crate) fn write_lecture(
+    bg: &mut MySqlBackend,
+    copy: &LectureCopy,
+    id: LectureId,
+    label: &str,
+) -> Result<(), mysql::Error> {
+    bg.transaction(|bg| {
+        bg.insert(
+            "lectures",
+            vec![
+                id.into(),
+                label.into(),
+                0.into(),
+                copy.blind_grading.clone(),
+                Value::NULL,
+            ],
+        );
+        notifications::store(bg, id, &copy.notifications);
+        for q in &copy.questions {
+            let mut row = vec![id.into()];
+            row.extend(q.iter().cloned());
+            bg.insert("questions", row);
+        }
+        for item in &copy.rubric {
+            let mut row = vec![Value::NULL, id.into()];
+            row.extend(item.iter().cloned());
+            bg.insert("rubric_items", row);
+        }
+    })
+}
+
+/// Copies every lecture of another class deployment (`from`) into `to`, keeping lecture IDs.
+/// Lectures whose ID already exists in `to` are skipped. Returns the lines to report.
+pub(crate) fn clone_term(
+    from: &mut MySqlBackend,
+    to: &mut MySqlBackend,
+    dry_run: bool,
+) -> Vec<String> {
+    let mut report = vec![];
+    for num in lecture_ids(from) {
+        let copy = match read_lecture(from, num) {
+            Some(copy) => copy,
+            None => continue,
+        };
+        if lecture_exists(to, num) {
+            report.push(format!(
+                "skipped: lecture {} ({}) already exists",
+                num, copy.label
+            ));
+            continue;
+        }
+        if !dry_run {
+            if let Err(e) = write_lecture(to, &copy, copy.id, &copy.label) {
+                report.push(format!("failed: lecture {} ({}): {}", num, copy.label, e));
+                continue;
+            }
+        }
+        report.push(format!(
+            "{}: lecture {} ({}), {} questions",
+            if dry_run { "would copy" } else { "copied" },
+            num,
+            copy.label,
+            copy.questions.len()
+        ));
+    }
+    report
+}
+
+#[post("/clone/<num>", data = "<data>")]
+pub(crate) fn clone_lecture(
+    _adm: Admin,
+    num: LectureId,
+    data: Form<CloneLectureForm>,
+    backend: &State<Arc<Mutex<MySqlBackend>>>,
+) -> Result<Redirect, BadRequest<String>> {
+    let mut bg = backend.lock().unwrap();
+    let copy = read_lecture(&mut bg, num)
+        .ok_or_else(|| BadRequest(Some(format!("No such lecture: {}", num))))?;
+    if lecture_exists(&mut bg, data.lec_id) {
+        return Err(BadRequest(Some(format!(
+            "Lecture {} already exists",
+            data.lec_id
+        ))));
+    }
+    write_lecture(&mut bg, &copy, data.lec_id, &data.lec_label)
+        .map_err(|e| BadRequest(Some(format!("Copying lecture {} failed: {}", num, e))))?;
+    drop(bg);
+
+    Ok(Redirect::to(format!("/admin/lec/{}", data.lec_id)))
+}
@@ -1,7 +1,35 @@
+use std::convert::TryFrom;
 use std::fs;
 use std::io::{Error, ErrorKind, Read};
 use toml;
 
+/// How the SMTP connection is secured.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SmtpSecurity {
+    None,
+    /// Upgrade a plain connection with STARTTLS (usually port 587)
+    StartTls,
+    /// Connect over TLS from the start (usually port 465)
+    Tls,
+}
+
+/// How emails are delivered.
+#[derive(Debug, Clone)]
+pub enum EmailTransport {
+    /// Hand emails to the local `sendmail` binary (or the given command)
+    Sendmail { command: Option<String> },
+    /// Send emails through an SMTP server
+    Smtp {
+        host: String,
+        port: u16,
+        security: SmtpSecurity,
+        username: Option<String>,
+        password: Option<String>,
+    },
+    /// Write emails as `.eml` files into a directory (for development and testing)
+    File { dir: String },
+}
+
 #[derive(Debug, Clone)]
 pub struct Config {
     /// Textual identifier for class
@@ -22,8 +50,78 @@ pub struct Config {
     pub secret: String,
     /// Whether to send emails
     pub send_emails: bool,
-    /// Whether to reset and prime db 
+    /// How to send emails
+    pub email_transport: EmailTransport,
+    /// Sender address of all emails
+    pub email_sender: String,
+    /// Where replies to emails go, unless an email sets its own reply-to address
+    pub email_reply_to: Option<String>,
+    /// Whether to email students a receipt of their submitted answers
+    pub send_receipts: bool,
+    /// Whether to reset and prime db
     pub prime: bool,
+    /// Directory where uploaded answer files are stored
+    pub upload_dir: String,
+    /// Maximum size of an uploaded answer file (in bytes)
+    pub upload_max_size: u64,
+    /// File extensions accepted for uploads (empty to accept any file)
+    pub upload_types: Vec<String>,
+    /// Default scoring of lectures in the gradebook export ("participation", "answered" or
+    /// "score")
+    pub gradebook_scoring: String,
+    /// Hours between notification digests, for lectures that send them
+    pub digest_hours: u64,
+}
+
+impl Config {
+    /// Whether an address belongs to course staff or an admin rather than a student.
+    pub fn is_staff(&self, email: &str) -> bool {
+        self.admins.iter().any(|a| a == email) || self.staff.iter().any(|s| s == email)
+    }
+}
+
+fn parse_email_transport(value: &toml::Table) -> Result<EmailTransport, Error> {
+    let opt_str = |key: &str| value.get(key).map(|v| String::from(v.as_str().unwrap()));
+    let invalid = |msg: String| Error::new(ErrorKind::InvalidInput, msg);
+    match value
+        .get("email_transport")
+        .map(|v| v.as_str().unwrap())
+        .unwrap_or("sendmail")
+    {
+        "sendmail" => Ok(EmailTransport::Sendmail {
+            command: opt_str("sendmail_command"),
+        }),
+        "smtp" => {
+            let security = match opt_str("smtp_security").as_deref().unwrap_or("starttls") {
+                "none" => SmtpSecurity::None,
+                "starttls" => SmtpSecurity::StartTls,
+                "tls" => SmtpSecurity::Tls,
+                s => return Err(invalid(format!("unknown smtp_security: {}", s))),
+            };
+            let port = match value.get("smtp_port") {
+                Some(v) => v
+                    .as_integer()
+                    .and_then(|p| u16::try_from(p).ok())
+                    .ok_or_else(|| invalid(format!("invalid smtp_port: {}", v)))?,
+                None => match security {
+                    SmtpSecurity::Tls => 465,
+                    _ => 587,
+                },
+            };
+            Ok(EmailTransport::Smtp {
+                host: opt_str("smtp_host")
+                    .ok_or_else(|| invalid(String::from("smtp_host is required for SMTP")))?,
+                port: port,
+                security: security,
+                username: opt_str("smtp_username"),
+                password: opt_str("smtp_password"),
+            })
+        }
+        "file" => Ok(EmailTransport::File {
+            dir: opt_str("email_dir").unwrap_or_else(|| String::from("emails")),
+        }),
+        t => Err(invalid(format!("unknown email_transport: {}", t))),
+    }
 }
 
 pub(crate) fn parse(path: &str) -> Result<Config, Error> {
@@ -41,6 +139,17 @@ pub(crate) fn parse(path: &str) -> Result<Config, Error> {
         Some(v) => v,
     };
 
+    let digest_hours = value
+        .get("digest_hours")
+        .map(|v| v.as_integer().unwrap())
+        .unwrap_or(24);
+    if digest_hours < 1 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("digest_hours must be at least 1, got {}", digest_hours),
+        ));
+    }
+
     Ok(Config {
         class: value.get("class").unwrap().as_str().unwrap().into(),
         db_user: value.get("db_user").unwrap().as_str().unwrap().into(),
@@ -65,6 +174,41 @@ pub(crate) fn parse(path: &str) -> Result<Config, Error> {
         resource_dir: value.get("resource_dir").unwrap().as_str().unwrap().into(),
         secret: value.get("secret").unwrap().as_str().unwrap().into(),
         send_emails: value.get("send_emails").unwrap().as_bool().unwrap().into(),
+        email_transport: parse_email_transport(&value)?,
+        email_sender: value
+            .get("email_sender")
+            .map(|v| v.as_str().unwrap().into())
+            .unwrap_or_else(|| String::from("no-reply@csci2390-submit.cs.brown.edu")),
+        email_reply_to: value
+            .get("email_reply_to")
+            .map(|v| v.as_str().unwrap().into()),
+        send_receipts: value
+            .get("send_receipts")
+            .map(|v| v.as_bool().unwrap())
+            .unwrap_or(false),
         prime: value.get("prime").unwrap().as_bool().unwrap().into(),
+        upload_dir: value
+            .get("upload_dir")
+            .map(|v| v.as_str().unwrap().into())
+            .unwrap_or_else(|| String::from("uploads")),
+        upload_max_size: value
+            .get("upload_max_size")
+            .map(|v| v.as_integer().unwrap() as u64)
+            .unwrap_or(10 * 1024 * 1024),
+        upload_types: value
+            .get("upload_types")
+            .map(|v| {
+                v.as_slice()
+                    .unwrap()
+                    .into_iter()
+                    .map(|v| v.as_str().unwrap().into())
+                    .collect()
+            })
+            .unwrap_or_default(),
+        gradebook_scoring: value
+            .get("gradebook_scoring")
+            .map(|v| v.as_str().unwrap().into())
+            .unwrap_or_else(|| String::from("participation")),
+        digest_hours: digest_hours as u64,
     })
 }
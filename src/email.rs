@@ -1,32 +1,231 @@
+use crate::config::{Config, EmailTransport, SmtpSecurity};
 use lettre::sendmail::SendmailTransport;
-use lettre::Transport;
+use lettre::smtp::authentication::Credentials;
+use lettre::smtp::client::net::ClientTlsParameters;
+use lettre::smtp::{ClientSecurity, SmtpClient};
+use lettre::{SendableEmail, Transport};
 use lettre_email::Email;
+use native_tls::TlsConnector;
+use rocket_dyn_templates::handlebars::{self, Handlebars};
+use std::fmt;
+use std::path::Path;
 
+/// Email templates live in this subdirectory of the template directory. Each email `<name>`
+/// has a `<name>_subject.txt.hbs` and `<name>_text.txt.hbs`, and optionally a
+/// `<name>_html.html.hbs` that is sent as an HTML alternative to the plain text.
+const TEMPLATE_SUBDIR: &str = "email";
+
+/// Why an email could not be sent.
+#[derive(Debug)]
+pub(crate) enum EmailError {
+    /// The message itself is invalid (e.g., a malformed address)
+    Build(lettre_email::error::Error),
+    Sendmail(lettre::sendmail::error::Error),
+    Smtp(lettre::smtp::error::Error),
+    Tls(native_tls::Error),
+    /// Writing the message to a file failed
+    Io(std::io::Error),
+    /// The email template is missing or failed to render
+    Template(handlebars::RenderError),
+    /// The queued template context is not valid JSON
+    Context(serde_json::Error),
+}
+
+impl fmt::Display for EmailError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmailError::Build(e) => write!(f, "invalid email: {}", e),
+            EmailError::Sendmail(e) => write!(f, "sendmail failed: {}", e),
+            EmailError::Smtp(e) => write!(f, "SMTP failed: {}", e),
+            EmailError::Tls(e) => write!(f, "TLS setup failed: {}", e),
+            EmailError::Io(e) => write!(f, "writing email failed: {}", e),
+            EmailError::Template(e) => write!(f, "rendering email failed: {}", e),
+            EmailError::Context(e) => write!(f, "invalid email context: {}", e),
+        }
+    }
+}
+
+impl From<lettre_email::error::Error> for EmailError {
+    fn from(e: lettre_email::error::Error) -> Self {
+        EmailError::Build(e)
+    }
+}
+
+impl From<lettre::sendmail::error::Error> for EmailError {
+    fn from(e: lettre::sendmail::error::Error) -> Self {
+        EmailError::Sendmail(e)
+    }
+}
+
+impl From<lettre::smtp::error::Error> for EmailError {
+    fn from(e: lettre::smtp::error::Error) -> Self {
+        EmailError::Smtp(e)
+    }
+}
+
+impl From<native_tls::Error> for EmailError {
+    fn from(e: native_tls::Error) -> Self {
+        EmailError::Tls(e)
+    }
+}
+
+impl From<std::io::Error> for EmailError {
+    fn from(e: std::io::Error) -> Self {
+        EmailError::Io(e)
+    }
+}
+
+impl From<handlebars::RenderError> for EmailError {
+    fn from(e: handlebars::RenderError) -> Self {
+        EmailError::Template(e)
+    }
+}
+
+impl From<serde_json::Error> for EmailError {
+    fn from(e: serde_json::Error) -> Self {
+        EmailError::Context(e)
+    }
+}
+
+/// A rendered email.
+pub(crate) struct Message {
+    pub subject: String,
+    pub text: String,
+    pub html: Option<String>,
+}
+
+/// The email templates, loaded from the template directory.
+pub(crate) struct Templates {
+    /// Subjects and plain text bodies, rendered without HTML escaping
+    text: Handlebars<'static>,
+    html: Handlebars<'static>,
+}
+
+impl Templates {
+    pub(crate) fn load(template_dir: &str) -> Templates {
+        let dir = Path::new(template_dir).join(TEMPLATE_SUBDIR);
+        let mut text = Handlebars::new();
+        text.register_escape_fn(handlebars::no_escape);
+        text.register_templates_directory(".txt.hbs", &dir)
+            .expect("failed to load email templates!");
+        let mut html = Handlebars::new();
+        html.register_templates_directory(".html.hbs", &dir)
+            .expect("failed to load email templates!");
+        Templates {
+            text: text,
+            html: html,
+        }
+    }
+
+    /// Renders the email `name` with the given context.
+    pub(crate) fn render(
+        &self,
+        name: &str,
+        context: &serde_json::Value,
+    ) -> Result<Message, EmailError> {
+        let html_name = format!("{}_html", name);
+        Ok(Message {
+            subject: self
+                .text
+                .render(&format!("{}_subject", name), context)?
+                .trim()
+                .to_string(),
+            text: self.text.render(&format!("{}_text", name), context)?,
+            html: if self.html.has_template(&html_name) {
+                Some(self.html.render(&html_name, context)?)
+            } else {
+                None
+            },
+        })
+    }
+}
+
+/// Writes a message to `<dir>/<message id>.eml`, for development and testing.
+fn write_file(dir: &str, email: SendableEmail) -> Result<(), EmailError> {
+    std::fs::create_dir_all(dir)?;
+    let path = Path::new(dir).join(format!("{}.eml", email.message_id()));
+    std::fs::write(path, email.message_to_string()?)?;
+    Ok(())
+}
+
+/// Sends a message through an SMTP server, authenticating if credentials are given.
+fn send_smtp(
+    host: &str,
+    port: u16,
+    security: SmtpSecurity,
+    credentials: Option<(&str, &str)>,
+    email: SendableEmail,
+) -> Result<(), EmailError> {
+    let tls = || -> Result<ClientTlsParameters, EmailError> {
+        Ok(ClientTlsParameters::new(
+            host.to_string(),
+            TlsConnector::builder().build()?,
+        ))
+    };
+    let security = match security {
+        SmtpSecurity::None => ClientSecurity::None,
+        SmtpSecurity::StartTls => ClientSecurity::Required(tls()?),
+        SmtpSecurity::Tls => ClientSecurity::Wrapper(tls()?),
+    };
+    let mut client = SmtpClient::new((host, port), security)?;
+    if let Some((username, password)) = credentials {
+        client = client.credentials(Credentials::new(username.to_string(), password.to_string()));
+    }
+    client.transport().send(email)?;
+    Ok(())
+}
+
+/// Sends a message from the configured sender. Replies go to `reply_to` if given, or else
+/// to the configured reply-to address.
 pub(crate) fn send(
     log: slog::Logger,
-    sender: String,
+    config: &Config,
     recipients: Vec<String>,
-    subject: String,
-    text: String,
-) -> Result<(), lettre::sendmail::error::Error> {
-    let mut mailer = SendmailTransport::new();
-
+    reply_to: Option<String>,
+    message: &Message,
+) -> Result<(), EmailError> {
     let mut builder = Email::builder()
-        .from(sender.clone())
-        .subject(subject.clone())
-        .text(text.clone());
+        .from(config.email_sender.clone())
+        .subject(message.subject.clone());
+    builder = match &message.html {
+        Some(html) => builder.alternative(html.clone(), message.text.clone()),
+        None => builder.text(message.text.clone()),
+    };
+    if let Some(reply_to) = reply_to.or_else(|| config.email_reply_to.clone()) {
+        builder = builder.reply_to(reply_to);
+    }
     for recipient in recipients {
         builder = builder.to(recipient);
     }
 
-    debug!(log, "Email: Subject {}\nText: {}!", subject, text);
+    debug!(
+        log,
+        "Email: Subject {}\nText: {}!", message.subject, message.text
+    );
 
-    let email = builder.build();
-    match email {
-        Ok(result) => mailer.send(result.into())?,
-        Err(e) => {
-            println!("couldn't construct email: {}", e);
+    let email: SendableEmail = builder.build()?.into();
+    match &config.email_transport {
+        EmailTransport::Sendmail { command } => {
+            let mut mailer = match command {
+                Some(command) => SendmailTransport::new_with_command(command.as_str()),
+                None => SendmailTransport::new(),
+            };
+            mailer.send(email)?;
+        }
+        EmailTransport::Smtp {
+            host,
+            port,
+            security,
+            username,
+            password,
+        } => {
+            let credentials = match (username, password) {
+                (Some(u), Some(p)) => Some((u.as_str(), p.as_str())),
+                _ => None,
+            };
+            send_smtp(host, *port, *security, credentials, email)?
         }
+        EmailTransport::File { dir } => write_file(dir, email)?,
     }
 
     Ok(())
@@ -0,0 +1,295 @@
+use crate::admin::Admin;
+use crate::backend::{MySqlBackend, Value};
+use crate::config::Config;
+use crate::csv;
+use crate::grading;
+use crate::questions::{self, LectureId, QuestionId};
+use chrono::naive::NaiveDateTime;
+use mysql::from_value;
+use rocket::futures::stream::Stream;
+use rocket::http::{ContentType, Header};
+use rocket::response::stream::TextStream;
+use rocket::State;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+/// Number of answers fetched from the database at a time while streaming an export.
+const PAGE_SIZE: u64 = 500;
+
+const CSV_HEADER: [&str; 8] = [
+    "user",
+    "lecture",
+    "question_id",
+    "question_prompt",
+    "answer",
+    "submitted_at",
+    "late",
+    "grade",
+];
+
+/// A streamed export, sent to the browser as an attachment.
+#[derive(Responder)]
+pub(crate) struct Export<R> {
+    inner: R,
+    content_type: ContentType,
+    disposition: Header<'static>,
+}
+
+impl<R> Export<R> {
+    pub(crate) fn new(inner: R, content_type: ContentType, filename: &str) -> Self {
+        Export {
+            inner: inner,
+            content_type: content_type,
+            disposition: Header::new(
+                "Content-Disposition",
+                format!("attachment; filename=\"{}\"", filename),
+            ),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ExportRow {
+    user: String,
+    lecture: LectureId,
+    question_id: QuestionId,
+    question_prompt: Option<String>,
+    answer: String,
+    submitted_at: Option<NaiveDateTime>,
+    late: bool,
+    grade: Option<f64>,
+}
+
+impl ExportRow {
+    fn to_csv(&self) -> String {
+        csv::record(&[
+            self.user.clone(),
+            self.lecture.to_string(),
+            self.question_id.to_string(),
+            self.question_prompt.clone().unwrap_or_default(),
+            self.answer.clone(),
+            self.submitted_at
+                .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+                .unwrap_or_default(),
+            self.late.to_string(),
+            self.grade.map(|g| g.to_string()).unwrap_or_default(),
+        ])
+    }
+}
+
+/// Lectures whose students are currently shown by pseudonym; exports keep them hidden too.
+fn blind_lectures(backend: &Arc<Mutex<MySqlBackend>>) -> HashSet<u64> {
+    let mut bg = backend.lock().unwrap();
+    let ids: Vec<u64> = bg
+        .prep_exec("SELECT id FROM lectures", vec![])
+        .into_iter()
+        .map(|r| from_value(r[0].clone()))
+        .collect();
+    ids.into_iter()
+        .filter(|id| grading::is_blind(&mut bg, *id))
+        .collect()
+}
+
+/// The key of the last answer on a page; the next page starts after it.
+type PageKey = (String, LectureId, QuestionId);
+
+/// Fetches the page of answers after `after`, either of one lecture or of the whole course, in
+/// primary key order. Paging by key rather than by offset means answers submitted or deleted
+/// between pages neither repeat nor drop other answers from the export.
+fn fetch_page(
+    backend: &Arc<Mutex<MySqlBackend>>,
+    config: &Config,
+    lec: Option<LectureId>,
+    blind: &HashSet<u64>,
+    after: &Option<PageKey>,
+) -> (Vec<ExportRow>, Option<PageKey>) {
+    let mut params: Vec<Value> = vec![];
+    let mut conds = vec![];
+    if let Some(num) = lec {
+        params.push(num.into());
+        conds.push("answers.lec = ?");
+    }
+    if let Some((email, lec, q)) = after {
+        params.push(email.clone().into());
+        params.push((*lec).into());
+        params.push((*q).into());
+        conds.push("(answers.email, answers.lec, answers.q) > (?, ?, ?)");
+    }
+    let filter = if conds.is_empty() {
+        String::new()
+    } else {
+        format!("WHERE {}", conds.join(" AND "))
+    };
+    params.push(PAGE_SIZE.into());
+    let sql = format!(
+        "SELECT answers.email, answers.lec, answers.q, questions.question, answers.answer, \
+         answers.submitted_at, lectures.deadline, grades.score, answers.score \
+         FROM answers \
+         LEFT JOIN questions ON (answers.lec = questions.lec AND answers.q = questions.q) \
+         LEFT JOIN lectures ON (answers.lec = lectures.id) \
+         LEFT JOIN grades ON (answers.email = grades.email \
+         AND answers.lec = grades.lec AND answers.q = grades.q) \
+         {} ORDER BY answers.email, answers.lec, answers.q LIMIT ?",
+        filter
+    );
+    let res = {
+        let mut bg = backend.lock().unwrap();
+        bg.prep_exec(&sql, params)
+    };
+
+    let last = res.last().map(|r| {
+        (
+            from_value(r[0].clone()),
+            from_value(r[1].clone()),
+            from_value(r[2].clone()),
+        )
+    });
+    let rows = res
+        .into_iter()
+        .map(|r| {
+            let email: String = from_value(r[0].clone());
+            let lecture: u64 = from_value(r[1].clone());
+            let submitted_at = questions::opt_datetime(&r[5]);
+            let deadline = questions::opt_datetime(&r[6]);
+            let opt_score = |v: &Value| {
+                if *v == Value::NULL {
+                    None
+                } else {
+                    Some(from_value::<f64>(v.clone()))
+                }
+            };
+            ExportRow {
+                user: if blind.contains(&lecture) {
+                    grading::pseudonym(config, lecture, &email)
+                } else {
+                    email
+                },
+                lecture: lecture,
+                question_id: from_value(r[2].clone()),
+                question_prompt: if r[3] == Value::NULL {
+                    None
+                } else {
+                    Some(from_value(r[3].clone()))
+                },
+                answer: from_value(r[4].clone()),
+                submitted_at: submitted_at,
+                late: match (submitted_at, deadline) {
+                    (Some(s), Some(d)) => s > d,
+                    _ => false,
+                },
+                // a manual grade overrides the automatic score
+                grade: opt_score(&r[7]).or_else(|| opt_score(&r[8])),
+            }
+        })
+        .collect();
+    (rows, last)
+}
+
+/// Streams answers as CSV, fetching them a page at a time so that the backend lock is never
+/// held for long and large courses are not buffered in memory.
+fn csv_stream(
+    backend: Arc<Mutex<MySqlBackend>>,
+    config: Config,
+    lec: Option<LectureId>,
+) -> TextStream![String] {
+    let blind = blind_lectures(&backend);
+    TextStream! {
+        yield csv::record(&CSV_HEADER);
+        let mut after = None;
+        loop {
+            let (rows, last) = fetch_page(&backend, &config, lec, &blind, &after);
+            if rows.is_empty() {
+                break;
+            }
+            after = last;
+            for row in rows {
+                yield row.to_csv();
+            }
+        }
+    }
+}
+
+/// Streams answers as a JSON array of objects with the same fields as the CSV columns.
+fn json_stream(
+    backend: Arc<Mutex<MySqlBackend>>,
+    config: Config,
+    lec: Option<LectureId>,
+) -> TextStream![String] {
+    let blind = blind_lectures(&backend);
+    TextStream! {
+        yield String::from("[");
+        let mut after = None;
+        let mut first = true;
+        loop {
+            let (rows, last) = fetch_page(&backend, &config, lec, &blind, &after);
+            if rows.is_empty() {
+                break;
+            }
+            after = last;
+            for row in rows {
+                let sep = if first { "" } else { "," };
+                first = false;
+                yield format!(
+                    "{}\n{}",
+                    sep,
+                    serde_json::to_string(&row).expect("failed to serialize answer")
+                );
+            }
+        }
+        yield String::from("\n]\n");
+    }
+}
+
+#[get("/<num>/export.csv")]
+pub(crate) fn lecture_csv(
+    _adm: Admin,
+    num: LectureId,
+    backend: &State<Arc<Mutex<MySqlBackend>>>,
+    config: &State<Config>,
+) -> Export<TextStream<impl Stream<Item = String>>> {
+    Export::new(
+        csv_stream(backend.inner().clone(), config.inner().clone(), Some(num)),
+        ContentType::CSV,
+        &format!("{}-lecture-{}.csv", config.class, num),
+    )
+}
+
+#[get("/<num>/export.json")]
+pub(crate) fn lecture_json(
+    _adm: Admin,
+    num: LectureId,
+    backend: &State<Arc<Mutex<MySqlBackend>>>,
+    config: &State<Config>,
+) -> Export<TextStream<impl Stream<Item = String>>> {
+    Export::new(
+        json_stream(backend.inner().clone(), config.inner().clone(), Some(num)),
+        ContentType::JSON,
+        &format!("{}-lecture-{}.json", config.class, num),
+    )
+}
+
+#[get("/export.csv")]
+pub(crate) fn course_csv(
+    _adm: Admin,
+    backend: &State<Arc<Mutex<MySqlBackend>>>,
+    config: &State<Config>,
+) -> Export<TextStream<impl Stream<Item = String>>> {
+    Export::new(
+        csv_stream(backend.inner().clone(), config.inner().clone(), None),
+        ContentType::CSV,
+        &format!("{}-answers.csv", config.class),
+    )
+}
+
+#[get("/export.json")]
+pub(crate) fn course_json(
+    _adm: Admin,
+    backend: &State<Arc<Mutex<MySqlBackend>>>,
+    config: &State<Config>,
+) -> Export<TextStream<impl Stream<Item = String>>> {
+    Export::new(
+        json_stream(backend.inner().clone(), config.inner().clone(), None),
+        ContentType::JSON,
+        &format!("{}-answers.json", config.class),
+    )
+}
@@ -0,0 +1,235 @@
+use crate::admin::Admin;
+use crate::backend::{MySqlBackend, Value};
+use crate::config::Config;
+use crate::csv;
+use crate::exports::Export;
+use crate::roster;
+use mysql::from_value;
+use rocket::http::ContentType;
+use rocket::response::status::BadRequest;
+use rocket::State;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// How a student's work on a lecture turns into a gradebook entry.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Scoring {
+    /// 1 if the student answered every question, otherwise 0
+    Participation,
+    /// The number of questions answered
+    Answered,
+    /// The sum of the student's grades (manual grades, falling back to automatic scores)
+    Score,
+}
+
+impl Scoring {
+    fn from_name(name: &str) -> Option<Scoring> {
+        match name {
+            "participation" => Some(Scoring::Participation),
+            "answered" => Some(Scoring::Answered),
+            "score" => Some(Scoring::Score),
+            _ => None,
+        }
+    }
+}
+
+/// The LMS whose grade import format to produce.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    Canvas,
+    Gradescope,
+}
+
+impl Format {
+    fn from_name(name: &str) -> Option<Format> {
+        match name {
+            "canvas" => Some(Format::Canvas),
+            "gradescope" => Some(Format::Gradescope),
+            _ => None,
+        }
+    }
+}
+
+struct Lecture {
+    id: u64,
+    label: String,
+    num_qs: u64,
+    max_score: f64,
+}
+
+impl Lecture {
+    fn points_possible(&self, scoring: Scoring) -> f64 {
+        match scoring {
+            Scoring::Participation => 1.0,
+            Scoring::Answered => self.num_qs as f64,
+            Scoring::Score => self.max_score,
+        }
+    }
+}
+
+/// Builds the gradebook CSV: one row per student (see `roster::students`), one column per
+/// lecture. Canvas matches rows to its students by SIS User ID, so a Canvas export fails if the
+/// roster lacks the SIS ID of any student.
+fn gradebook(
+    bg: &mut MySqlBackend,
+    config: &Config,
+    format: Format,
+    scoring: Scoring,
+) -> Result<String, String> {
+    let lres = bg.prep_exec(
+        "SELECT lectures.id, lectures.label, lec_qcount.qcount \
+         FROM lectures LEFT JOIN lec_qcount ON (lectures.id = lec_qcount.lec) ORDER BY lectures.id",
+        vec![],
+    );
+    let pres = bg.prep_exec(
+        "SELECT lec, SUM(COALESCE(points, 1)) FROM questions GROUP BY lec",
+        vec![],
+    );
+    let answered_res = bg.prep_exec("SELECT email, lec, answered FROM lec_answered", vec![]);
+    let scores_res = bg.prep_exec(
+        "SELECT answers.email, answers.lec, SUM(COALESCE(grades.score, answers.score, 0)) \
+         FROM answers LEFT JOIN grades ON (answers.email = grades.email \
+         AND answers.lec = grades.lec AND answers.q = grades.q) \
+         GROUP BY answers.email, answers.lec",
+        vec![],
+    );
+
+    let max_scores: HashMap<u64, f64> = pres
+        .into_iter()
+        .map(|r| (from_value(r[0].clone()), from_value(r[1].clone())))
+        .collect();
+    let lectures: Vec<Lecture> = lres
+        .into_iter()
+        .map(|r| {
+            let id: u64 = from_value(r[0].clone());
+            Lecture {
+                id: id,
+                label: from_value(r[1].clone()),
+                num_qs: if r[2] == Value::NULL {
+                    0
+                } else {
+                    from_value(r[2].clone())
+                },
+                max_score: max_scores.get(&id).cloned().unwrap_or(0.0),
+            }
+        })
+        .collect();
+    let students = roster::students(bg, config);
+    if format == Format::Canvas {
+        let missing: Vec<&str> = students
+            .iter()
+            .filter(|s| s.sis_id.is_none())
+            .map(|s| s.email.as_str())
+            .collect();
+        if !missing.is_empty() {
+            return Err(format!(
+                "Canvas needs the SIS ID of every student. Import a roster with SIS IDs for: {}",
+                missing.join(", ")
+            ));
+        }
+    }
+    let mut answered = HashMap::new();
+    for r in answered_res {
+        let key: (String, u64) = (from_value(r[0].clone()), from_value(r[1].clone()));
+        answered.insert(key, from_value::<u64>(r[2].clone()));
+    }
+    let mut scores = HashMap::new();
+    for r in scores_res {
+        let key: (String, u64) = (from_value(r[0].clone()), from_value(r[1].clone()));
+        scores.insert(key, from_value::<f64>(r[2].clone()));
+    }
+
+    let columns: Vec<String> = lectures
+        .iter()
+        .map(|l| format!("Lecture {}: {}", l.id, l.label))
+        .collect();
+    let mut out = String::new();
+    match format {
+        Format::Canvas => {
+            // Canvas expects its identifying columns first and a "Points Possible" row
+            let mut header = vec![
+                String::from("Student"),
+                String::from("ID"),
+                String::from("SIS User ID"),
+                String::from("SIS Login ID"),
+                String::from("Section"),
+            ];
+            header.extend(columns);
+            out.push_str(&csv::record(&header));
+            let mut points = vec![String::from("Points Possible")];
+            points.extend(vec![String::new(); 4]);
+            points.extend(
+                lectures
+                    .iter()
+                    .map(|l| l.points_possible(scoring).to_string()),
+            );
+            out.push_str(&csv::record(&points));
+        }
+        Format::Gradescope => {
+            let mut header = vec![String::from("Email")];
+            header.extend(columns);
+            out.push_str(&csv::record(&header));
+        }
+    }
+
+    for student in students {
+        let mut row = match format {
+            Format::Canvas => vec![
+                student
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| student.email.clone()),
+                String::new(),
+                student.sis_id.clone().unwrap_or_default(),
+                student.email.clone(),
+                String::new(),
+            ],
+            Format::Gradescope => vec![student.email.clone()],
+        };
+        for l in &lectures {
+            let key = (student.email.clone(), l.id);
+            let num_answered = answered.get(&key).cloned().unwrap_or(0);
+            let entry = match scoring {
+                Scoring::Participation => {
+                    if l.num_qs > 0 && num_answered >= l.num_qs {
+                        1.0
+                    } else {
+                        0.0
+                    }
+                }
+                Scoring::Answered => num_answered as f64,
+                Scoring::Score => scores.get(&key).cloned().unwrap_or(0.0),
+            };
+            row.push(entry.to_string());
+        }
+        out.push_str(&csv::record(&row));
+    }
+    Ok(out)
+}
+
+#[get("/?<format>&<scoring>")]
+pub(crate) fn export(
+    _adm: Admin,
+    format: Option<String>,
+    scoring: Option<String>,
+    backend: &State<Arc<Mutex<MySqlBackend>>>,
+    config: &State<Config>,
+) -> Result<Export<String>, BadRequest<String>> {
+    let format_name = format.unwrap_or_else(|| String::from("canvas"));
+    let format = Format::from_name(&format_name)
+        .ok_or_else(|| BadRequest(Some(format!("Unknown format: {}", format_name))))?;
+    let scoring_name = scoring.unwrap_or_else(|| config.gradebook_scoring.clone());
+    let scoring = Scoring::from_name(&scoring_name)
+        .ok_or_else(|| BadRequest(Some(format!("Unknown scoring: {}", scoring_name))))?;
+
+    let mut bg = backend.lock().unwrap();
+    let out = gradebook(&mut bg, config, format, scoring).map_err(|e| BadRequest(Some(e)));
+    drop(bg);
+    let out = out?;
+
+    Ok(Export::new(
+        out,
+        ContentType::CSV,
+        &format!("{}-gradebook-{}.csv", config.class, format_name),
+    ))
+}
@@ -0,0 +1,422 @@
+use crate::admin::Admin;
+use crate::apikey::ApiKey;
+use crate::backend::{MySqlBackend, Value};
+use crate::config::Config;
+use crate::groups;
+use crate::questions::{self, LectureId};
+use crate::rubric::{self, RubricItem};
+use chrono::Local;
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use mysql::from_value;
+use rocket::form::Form;
+use rocket::response::Redirect;
+use rocket::State;
+use rocket_dyn_templates::Template;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, FromForm)]
+pub(crate) struct GradeEntry {
+    score: Option<f64>,
+    comment: String,
+    rubric: Vec<u64>,
+}
+
+#[derive(Debug, FromForm)]
+pub(crate) struct GradeSubmission {
+    student: String,
+    grades: HashMap<u64, GradeEntry>,
+}
+
+#[derive(Debug, FromForm)]
+pub(crate) struct DeanonymizeForm {
+    pseudonym: String,
+}
+
+#[derive(Serialize)]
+struct DeanonymizeContext {
+    lec_id: LectureId,
+    pseudonym: String,
+    email: Option<String>,
+    parent: &'static str,
+}
+
+/// A student as shown to graders: their email, or a pseudonym while grading is blind.
+pub(crate) struct Student {
+    pub email: String,
+    pub display: String,
+}
+
+#[derive(Serialize)]
+struct GradeQuestion {
+    id: u64,
+    prompt: String,
+    points: f64,
+    answer: Option<String>,
+    auto_score: Option<f64>,
+    score: Option<f64>,
+    comment: Option<String>,
+    rubric: Vec<RubricItem>,
+}
+
+#[derive(Serialize)]
+struct GradingContext {
+    lec_id: LectureId,
+    pos: usize,
+    number: usize,
+    num_students: usize,
+    prev_url: Option<String>,
+    next_url: Option<String>,
+    student: String,
+    blind: bool,
+    questions: Vec<GradeQuestion>,
+    grades_released: bool,
+    parent: &'static str,
+}
+
+/// Whether the grades for a lecture have been released to students.
+pub(crate) fn grades_released(bg: &mut MySqlBackend, num: LectureId) -> bool {
+    let res = bg.prep_exec(
+        "SELECT grades_released FROM lectures WHERE id = ?",
+        vec![num.into()],
+    );
+    match res.get(0) {
+        Some(r) if r[0] != Value::NULL => from_value::<u64>(r[0].clone()) != 0,
+        _ => false,
+    }
+}
+
+/// Whether student identities are hidden from graders: the lecture is graded blind and its
+/// grades have not been released yet.
+pub(crate) fn is_blind(bg: &mut MySqlBackend, num: LectureId) -> bool {
+    let res = bg.prep_exec(
+        "SELECT blind_grading, grades_released FROM lectures WHERE id = ?",
+        vec![num.into()],
+    );
+    match res.get(0) {
+        Some(r) => {
+            let flag = |v: &Value| *v != Value::NULL && from_value::<u64>(v.clone()) != 0;
+            flag(&r[0]) && !flag(&r[1])
+        }
+        None => false,
+    }
+}
+
+/// A stable pseudonym for a student in a lecture. It is derived from the secret, so it cannot
+/// be reversed without access to the server.
+pub(crate) fn pseudonym(config: &Config, num: LectureId, email: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.input_str(&config.secret);
+    hasher.input_str(&format!("{}", num));
+    hasher.input_str(email);
+    format!("student-{}", &hasher.result_str()[..10])
+}
+
+/// Students who answered at least one question in a lecture, in grading order. While grading
+/// is blind, students are shown and ordered by pseudonym.
+pub(crate) fn students(bg: &mut MySqlBackend, config: &Config, num: LectureId) -> Vec<Student> {
+    let blind = is_blind(bg, num);
+    let mut students: Vec<_> = bg
+        .prep_exec(
+            "SELECT DISTINCT email FROM answers WHERE lec = ?",
+            vec![num.into()],
+        )
+        .into_iter()
+        .map(|r| {
+            let email: String = from_value(r[0].clone());
+            Student {
+                display: if blind {
+                    pseudonym(config, num, &email)
+                } else {
+                    email.clone()
+                },
+                email: email,
+            }
+        })
+        .collect();
+    students.sort_by(|a, b| a.display.cmp(&b.display));
+    students
+}
+
+/// Finds the email of the student shown to graders as `display`.
+pub(crate) fn resolve_student(
+    bg: &mut MySqlBackend,
+    config: &Config,
+    num: LectureId,
+    display: &str,
+) -> Option<String> {
+    students(bg, config, num)
+        .into_iter()
+        .find(|s| s.display == display)
+        .map(|s| s.email)
+}
+
+#[get("/<num>")]
+pub(crate) fn grade_start(_adm: Admin, num: LectureId) -> Redirect {
+    Redirect::to(format!("/grade/{}/0", num))
+}
+
+#[get("/<num>/<pos>")]
+pub(crate) fn grade(
+    _adm: Admin,
+    num: LectureId,
+    pos: usize,
+    backend: &State<Arc<Mutex<MySqlBackend>>>,
+    config: &State<Config>,
+) -> Option<Template> {
+    let mut bg = backend.lock().unwrap();
+    let students = students(&mut bg, config, num);
+    let student = &students.get(pos)?.email;
+
+    let mut qres = bg.prep_exec("SELECT * FROM questions WHERE lec = ?", vec![num.into()]);
+    let ares = bg.prep_exec(
+        "SELECT q, answer, score FROM answers WHERE lec = ? AND email = ?",
+        vec![num.into(), student.clone().into()],
+    );
+    let gres = bg.prep_exec(
+        "SELECT q, score, comment FROM grades WHERE lec = ? AND email = ?",
+        vec![num.into(), student.clone().into()],
+    );
+    let released = grades_released(&mut bg, num);
+    let blind = is_blind(&mut bg, num);
+    let mut rubrics = rubric::lecture_items(&mut bg, num);
+    let applied = rubric::applied(&mut bg, num, student);
+    drop(bg);
+
+    let mut answers = HashMap::new();
+    for r in ares {
+        let id: u64 = from_value(r[0].clone());
+        let answer: String = from_value(r[1].clone());
+        let auto_score: Option<f64> = if r[2] == Value::NULL {
+            None
+        } else {
+            Some(from_value(r[2].clone()))
+        };
+        answers.insert(id, (answer, auto_score));
+    }
+    let mut grades = HashMap::new();
+    for r in gres {
+        let id: u64 = from_value(r[0].clone());
+        let score: Option<f64> = if r[1] == Value::NULL {
+            None
+        } else {
+            Some(from_value(r[1].clone()))
+        };
+        let comment: Option<String> = if r[2] == Value::NULL {
+            None
+        } else {
+            Some(from_value(r[2].clone()))
+        };
+        grades.insert(id, (score, comment));
+    }
+
+    qres.sort_by_key(|r| questions::question_order(r));
+    let qs: Vec<_> = qres
+        .into_iter()
+        .map(|r| {
+            let id: u64 = from_value(r[1].clone());
+            let (answer, auto_score) = match answers.remove(&id) {
+                Some((a, s)) => (Some(a), s),
+                None => (None, None),
+            };
+            let (score, comment) = grades.remove(&id).unwrap_or((None, None));
+            let mut items = rubrics.remove(&id).unwrap_or_default();
+            if let Some(applied) = applied.get(&id) {
+                for item in items.iter_mut() {
+                    item.applied = applied.contains(&item.id);
+                }
+            }
+            GradeQuestion {
+                id: id,
+                prompt: from_value(r[2].clone()),
+                points: questions::question_points(&r),
+                answer: answer,
+                auto_score: auto_score,
+                // start from the automatic score if nobody has graded this answer yet
+                score: score.or(auto_score),
+                comment: comment,
+                rubric: items,
+            }
+        })
+        .collect();
+
+    let ctx = GradingContext {
+        lec_id: num,
+        pos: pos,
+        number: pos + 1,
+        num_students: students.len(),
+        prev_url: if pos > 0 {
+            Some(format!("/grade/{}/{}", num, pos - 1))
+        } else {
+            None
+        },
+        next_url: if pos + 1 < students.len() {
+            Some(format!("/grade/{}/{}", num, pos + 1))
+        } else {
+            None
+        },
+        student: students[pos].display.clone(),
+        blind: blind,
+        questions: qs,
+        grades_released: released,
+        parent: "layout",
+    };
+    Some(Template::render("grade", &ctx))
+}
+
+/// What the grading form shows for an answer before the grader changes anything: the stored
+/// score (or else the automatic score), the comment and the applied rubric items (sorted).
+fn shown_grade(
+    bg: &mut MySqlBackend,
+    num: LectureId,
+    q: u64,
+    email: &str,
+) -> (Option<f64>, String, Vec<u64>) {
+    let opt_score = |v: &Value| {
+        if *v == Value::NULL {
+            None
+        } else {
+            Some(from_value::<f64>(v.clone()))
+        }
+    };
+    let gres = bg.prep_exec(
+        "SELECT score, comment FROM grades WHERE email = ? AND lec = ? AND q = ?",
+        vec![email.into(), num.into(), q.into()],
+    );
+    let ares = bg.prep_exec(
+        "SELECT score FROM answers WHERE email = ? AND lec = ? AND q = ?",
+        vec![email.into(), num.into(), q.into()],
+    );
+    let auto_score = ares.get(0).and_then(|r| opt_score(&r[0]));
+    let (score, comment) = match gres.get(0) {
+        Some(r) => (
+            opt_score(&r[0]).or(auto_score),
+            if r[1] == Value::NULL {
+                String::new()
+            } else {
+                from_value(r[1].clone())
+            },
+        ),
+        None => (auto_score, String::new()),
+    };
+    let mut applied: Vec<u64> = bg
+        .prep_exec(
+            "SELECT item FROM rubric_applied WHERE email = ? AND lec = ? AND q = ?",
+            vec![email.into(), num.into(), q.into()],
+        )
+        .into_iter()
+        .map(|r| from_value(r[0].clone()))
+        .collect();
+    applied.sort();
+    (score, comment, applied)
+}
+
+#[post("/<num>/<pos>", data = "<data>")]
+pub(crate) fn grade_submit(
+    _adm: Admin,
+    apikey: ApiKey,
+    num: LectureId,
+    pos: usize,
+    data: Form<GradeSubmission>,
+    backend: &State<Arc<Mutex<MySqlBackend>>>,
+    config: &State<Config>,
+) -> Option<Redirect> {
+    let mut bg = backend.lock().unwrap();
+    let student = resolve_student(&mut bg, config, num, &data.student)?;
+    let ts: Value = Local::now().naive_local().into();
+    // group members share a submission, so they also share its grade
+    let members = groups::members(&mut bg, num, &student);
+    for member in &members {
+        for (id, entry) in &data.grades {
+            // the form is pre-filled, so only store what the grader changed; an untouched
+            // automatic score must stay automatic to follow later answer key changes
+            let mut ticked = entry.rubric.clone();
+            ticked.sort();
+            if shown_grade(&mut bg, num, *id, member)
+                == (entry.score, entry.comment.clone(), ticked)
+            {
+                continue;
+            }
+            // ticking rubric items overrides the score entered by hand
+            let score = rubric::apply(&mut bg, num, *id, member, &entry.rubric).or(entry.score);
+            bg.replace(
+                "grades",
+                vec![
+                    member.clone().into(),
+                    num.into(),
+                    (*id).into(),
+                    score.into(),
+                    entry.comment.clone().into(),
+                    apikey.user.clone().into(),
+                    ts.clone(),
+                ],
+            );
+        }
+    }
+    let num_students = students(&mut bg, config, num).len();
+    drop(bg);
+
+    if pos + 1 < num_students {
+        Some(Redirect::to(format!("/grade/{}/{}", num, pos + 1)))
+    } else {
+        Some(Redirect::to(format!("/answers/{}", num)))
+    }
+}
+
+/// Records that `grader` learned the identity behind a pseudonym in a blind lecture.
+pub(crate) fn log_deanonymization(
+    bg: &mut MySqlBackend,
+    grader: &str,
+    num: LectureId,
+    pseudonym: &str,
+    email: &str,
+) {
+    warn!(
+        bg.log,
+        "{} de-anonymized {} in lecture {} as {}", grader, pseudonym, num, email
+    );
+    bg.insert(
+        "deanonymizations",
+        vec![
+            grader.into(),
+            num.into(),
+            pseudonym.into(),
+            email.into(),
+            Local::now().naive_local().into(),
+        ],
+    );
+}
+
+#[post("/deanonymize/<num>", data = "<data>")]
+pub(crate) fn deanonymize(
+    _adm: Admin,
+    apikey: ApiKey,
+    num: LectureId,
+    data: Form<DeanonymizeForm>,
+    backend: &State<Arc<Mutex<MySqlBackend>>>,
+    config: &State<Config>,
+) -> Template {
+    let mut bg = backend.lock().unwrap();
+    let email = bg
+        .prep_exec(
+            "SELECT DISTINCT email FROM answers WHERE lec = ?",
+            vec![num.into()],
+        )
+        .into_iter()
+        .map(|r| from_value::<String>(r[0].clone()))
+        .find(|e| pseudonym(config, num, e) == data.pseudonym);
+
+    if let Some(ref email) = email {
+        log_deanonymization(&mut bg, &apikey.user, num, &data.pseudonym, email);
+    }
+    drop(bg);
+
+    let ctx = DeanonymizeContext {
+        lec_id: num,
+        pseudonym: data.pseudonym.clone(),
+        email: email,
+        parent: "layout",
+    };
+    Template::render("deanonymize", &ctx)
+}
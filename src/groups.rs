@@ -0,0 +1,468 @@
+use crate::admin::Admin;
+use crate::apikey::ApiKey;
+use crate::backend::{MySqlBackend, Value};
+use crate::questions::{self, LectureId};
+use chrono::Local;
+use mysql::from_value;
+use rocket::form::Form;
+use rocket::response::status::BadRequest;
+use rocket::response::Redirect;
+use rocket::State;
+use rocket_dyn_templates::Template;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, FromForm)]
+pub(crate) struct CreateGroupForm {
+    name: String,
+}
+
+#[derive(Debug, FromForm)]
+pub(crate) struct InviteForm {
+    email: String,
+}
+
+#[derive(Debug, FromForm)]
+pub(crate) struct GroupForm {
+    group: u64,
+    /// Whether a student who already answered agrees to replace their answers with the group's
+    replace_answers: bool,
+}
+
+#[derive(Debug, FromForm)]
+pub(crate) struct StaffGroupForm {
+    name: String,
+    /// Member emails, one per line
+    members: String,
+}
+
+#[derive(Serialize)]
+pub(crate) struct GroupMember {
+    pub email: String,
+    pub accepted: bool,
+}
+
+#[derive(Serialize)]
+pub(crate) struct Group {
+    pub id: u64,
+    pub name: String,
+    pub members: Vec<GroupMember>,
+}
+
+#[derive(Serialize)]
+struct GroupsContext {
+    lec_id: LectureId,
+    group: Option<Group>,
+    invitations: Vec<Group>,
+    /// Whether the student has answers of their own that joining a group would replace
+    has_answers: bool,
+    parent: &'static str,
+}
+
+fn group_by_id(bg: &mut MySqlBackend, id: u64) -> Option<Group> {
+    let res = bg.prep_exec("SELECT name FROM lec_groups WHERE id = ?", vec![id.into()]);
+    let name: String = from_value(res.get(0)?[0].clone());
+    let members = bg
+        .prep_exec(
+            "SELECT email, accepted FROM group_members WHERE group_id = ? ORDER BY email",
+            vec![id.into()],
+        )
+        .into_iter()
+        .map(|r| GroupMember {
+            email: from_value(r[0].clone()),
+            accepted: from_value::<u64>(r[1].clone()) != 0,
+        })
+        .collect();
+    Some(Group {
+        id: id,
+        name: name,
+        members: members,
+    })
+}
+
+/// The group a student belongs to in a lecture (not counting pending invitations).
+fn group_id(bg: &mut MySqlBackend, num: LectureId, email: &str) -> Option<u64> {
+    let res = bg.prep_exec(
+        "SELECT lec_groups.id FROM lec_groups \
+         JOIN group_members ON (lec_groups.id = group_members.group_id) \
+         WHERE lec_groups.lec = ? AND group_members.email = ? AND group_members.accepted = 1",
+        vec![num.into(), email.into()],
+    );
+    res.get(0).map(|r| from_value(r[0].clone()))
+}
+
+/// The group a student belongs to in a lecture, with its members and pending invitations.
+pub(crate) fn user_group(bg: &mut MySqlBackend, num: LectureId, email: &str) -> Option<Group> {
+    let id = group_id(bg, num, email)?;
+    group_by_id(bg, id)
+}
+
+/// All groups in a lecture.
+pub(crate) fn lecture_groups(bg: &mut MySqlBackend, num: LectureId) -> Vec<Group> {
+    let ids: Vec<u64> = bg
+        .prep_exec(
+            "SELECT id FROM lec_groups WHERE lec = ? ORDER BY id",
+            vec![num.into()],
+        )
+        .into_iter()
+        .map(|r| from_value(r[0].clone()))
+        .collect();
+    ids.into_iter()
+        .filter_map(|id| group_by_id(bg, id))
+        .collect()
+}
+
+/// The students who share a submission with `email` in a lecture: the members of their group,
+/// or just the student themselves if they are not in one.
+pub(crate) fn members(bg: &mut MySqlBackend, num: LectureId, email: &str) -> Vec<String> {
+    match user_group(bg, num, email) {
+        Some(group) => group
+            .members
+            .into_iter()
+            .filter(|m| m.accepted)
+            .map(|m| m.email)
+            .collect(),
+        None => vec![email.to_string()],
+    }
+}
+
+/// Whether a student has answered any question in a lecture.
+fn has_answers(bg: &mut MySqlBackend, num: LectureId, email: &str) -> bool {
+    !bg.prep_exec(
+        "SELECT q FROM answers WHERE lec = ? AND email = ? LIMIT 1",
+        vec![num.into(), email.into()],
+    )
+    .is_empty()
+}
+
+/// Creates a group and returns its id. Meant to run in a transaction together with adding the
+/// members; if the insert fails, the id is 0 and the transaction rolls back.
+fn create_group(bg: &mut MySqlBackend, num: LectureId, name: &str) -> u64 {
+    bg.insert("lec_groups", vec![Value::NULL, num.into(), name.into()]);
+    let res = bg.prep_exec("SELECT LAST_INSERT_ID()", vec![]);
+    res.get(0).map_or(0, |r| from_value(r[0].clone()))
+}
+
+/// Replaces `to`'s answers (and uploads) for a lecture with a copy of `from`'s.
+fn copy_answers(bg: &mut MySqlBackend, num: LectureId, from: &str, to: &str) {
+    for table in &["answers", "uploads"] {
+        // drop answers to questions `from` left blank too, so that both submissions match
+        bg.prep_exec(
+            &format!("DELETE FROM {} WHERE lec = ? AND email = ?", table),
+            vec![num.into(), to.into()],
+        );
+        let rows = bg.prep_exec(
+            &format!("SELECT * FROM {} WHERE lec = ? AND email = ?", table),
+            vec![num.into(), from.into()],
+        );
+        for mut r in rows {
+            r[0] = to.into();
+            bg.replace(table, r);
+        }
+    }
+}
+
+/// Gives a student who joins a group a copy of the group's existing answers, so that all
+/// members see the same submission.
+fn share_answers(bg: &mut MySqlBackend, num: LectureId, group: u64, email: &str) {
+    let other = bg.prep_exec(
+        "SELECT email FROM group_members WHERE group_id = ? AND accepted = 1 AND email != ?",
+        vec![group.into(), email.into()],
+    );
+    let other: String = match other.get(0) {
+        Some(r) => from_value(r[0].clone()),
+        None => return,
+    };
+    copy_answers(bg, num, &other, email);
+}
+
+/// Removes a student from a group, deleting the group once it has no members left.
+fn remove_member(bg: &mut MySqlBackend, group: u64, email: &str) {
+    bg.prep_exec(
+        "DELETE FROM group_members WHERE group_id = ? AND email = ?",
+        vec![group.into(), email.into()],
+    );
+    let res = bg.prep_exec(
+        "SELECT COUNT(*) FROM group_members WHERE group_id = ? AND accepted = 1",
+        vec![group.into()],
+    );
+    if from_value::<u64>(res[0][0].clone()) == 0 {
+        bg.prep_exec(
+            "DELETE FROM group_members WHERE group_id = ?",
+            vec![group.into()],
+        );
+        bg.prep_exec("DELETE FROM lec_groups WHERE id = ?", vec![group.into()]);
+    }
+}
+
+#[get("/<num>")]
+pub(crate) fn groups(
+    apikey: ApiKey,
+    num: LectureId,
+    backend: &State<Arc<Mutex<MySqlBackend>>>,
+) -> Template {
+    let mut bg = backend.lock().unwrap();
+    let group = user_group(&mut bg, num, &apikey.user);
+    let invited: Vec<u64> = bg
+        .prep_exec(
+            "SELECT lec_groups.id FROM lec_groups \
+             JOIN group_members ON (lec_groups.id = group_members.group_id) \
+             WHERE lec_groups.lec = ? AND group_members.email = ? AND group_members.accepted = 0",
+            vec![num.into(), apikey.user.clone().into()],
+        )
+        .into_iter()
+        .map(|r| from_value(r[0].clone()))
+        .collect();
+    let invitations = invited
+        .into_iter()
+        .filter_map(|id| group_by_id(&mut bg, id))
+        .collect();
+    let has_answers = has_answers(&mut bg, num, &apikey.user);
+    drop(bg);
+
+    let ctx = GroupsContext {
+        lec_id: num,
+        group: group,
+        invitations: invitations,
+        has_answers: has_answers,
+        parent: "layout",
+    };
+    Template::render("groups", &ctx)
+}
+
+#[post("/create/<num>", data = "<data>")]
+pub(crate) fn create(
+    apikey: ApiKey,
+    num: LectureId,
+    data: Form<CreateGroupForm>,
+    backend: &State<Arc<Mutex<MySqlBackend>>>,
+) -> Result<Redirect, BadRequest<String>> {
+    let mut bg = backend.lock().unwrap();
+    if group_id(&mut bg, num, &apikey.user).is_some() {
+        return Err(BadRequest(Some(
+            "You are already in a group for this lecture.".into(),
+        )));
+    }
+    bg.transaction(|bg| {
+        let group = create_group(bg, num, data.name.trim());
+        bg.insert(
+            "group_members",
+            vec![group.into(), apikey.user.clone().into(), 1.into()],
+        );
+    })
+    .map_err(|e| BadRequest(Some(format!("Creating the group failed: {}", e))))?;
+    drop(bg);
+
+    Ok(Redirect::to(format!("/groups/{}", num)))
+}
+
+#[post("/invite/<num>", data = "<data>")]
+pub(crate) fn invite(
+    apikey: ApiKey,
+    num: LectureId,
+    data: Form<InviteForm>,
+    backend: &State<Arc<Mutex<MySqlBackend>>>,
+) -> Result<Redirect, BadRequest<String>> {
+    let mut bg = backend.lock().unwrap();
+    let group = match group_id(&mut bg, num, &apikey.user) {
+        Some(group) => group,
+        None => {
+            return Err(BadRequest(Some(
+                "Create a group before inviting others.".into(),
+            )))
+        }
+    };
+    let email = data.email.trim();
+    let registered = bg.prep_exec(
+        "SELECT email FROM users WHERE email = ?",
+        vec![email.into()],
+    );
+    if registered.is_empty() {
+        return Err(BadRequest(Some(format!("{} is not registered.", email))));
+    }
+    if group_id(&mut bg, num, email).is_some() {
+        return Err(BadRequest(Some(format!(
+            "{} is already in a group for this lecture.",
+            email
+        ))));
+    }
+    bg.replace("group_members", vec![group.into(), email.into(), 0.into()]);
+    drop(bg);
+
+    Ok(Redirect::to(format!("/groups/{}", num)))
+}
+
+#[post("/accept/<num>", data = "<data>")]
+pub(crate) fn accept(
+    apikey: ApiKey,
+    num: LectureId,
+    data: Form<GroupForm>,
+    backend: &State<Arc<Mutex<MySqlBackend>>>,
+) -> Result<Redirect, BadRequest<String>> {
+    let mut bg = backend.lock().unwrap();
+    let invited = bg.prep_exec(
+        "SELECT group_members.email FROM group_members \
+         JOIN lec_groups ON (lec_groups.id = group_members.group_id) \
+         WHERE group_members.group_id = ? AND group_members.email = ? AND lec_groups.lec = ?",
+        vec![data.group.into(), apikey.user.clone().into(), num.into()],
+    );
+    if invited.is_empty() {
+        return Err(BadRequest(Some(
+            "You were not invited to this group.".into(),
+        )));
+    }
+    if group_id(&mut bg, num, &apikey.user).is_some() {
+        return Err(BadRequest(Some(
+            "Leave your current group before joining another.".into(),
+        )));
+    }
+    // joining replaces the student's answers, which must not change after the deadline
+    if let Some(deadline) = questions::lecture_deadline(&mut bg, num) {
+        if Local::now().naive_local() > deadline {
+            return Err(BadRequest(Some(format!(
+                "The deadline for lecture {} has passed, so you can no longer join a group.",
+                num
+            ))));
+        }
+    }
+    if !data.replace_answers && has_answers(&mut bg, num, &apikey.user) {
+        return Err(BadRequest(Some(
+            "Joining the group replaces your own answers with the group's. \
+             Confirm this on the invitation to join anyway."
+                .into(),
+        )));
+    }
+    bg.prep_exec(
+        "UPDATE group_members SET accepted = 1 WHERE group_id = ? AND email = ?",
+        vec![data.group.into(), apikey.user.clone().into()],
+    );
+    share_answers(&mut bg, num, data.group, &apikey.user);
+    drop(bg);
+
+    Ok(Redirect::to(format!("/groups/{}", num)))
+}
+
+#[post("/decline/<num>", data = "<data>")]
+pub(crate) fn decline(
+    apikey: ApiKey,
+    num: LectureId,
+    data: Form<GroupForm>,
+    backend: &State<Arc<Mutex<MySqlBackend>>>,
+) -> Redirect {
+    let mut bg = backend.lock().unwrap();
+    bg.prep_exec(
+        "DELETE FROM group_members WHERE group_id = ? AND email = ? AND accepted = 0",
+        vec![data.group.into(), apikey.user.clone().into()],
+    );
+    drop(bg);
+
+    Redirect::to(format!("/groups/{}", num))
+}
+
+#[post("/leave/<num>")]
+pub(crate) fn leave(
+    apikey: ApiKey,
+    num: LectureId,
+    backend: &State<Arc<Mutex<MySqlBackend>>>,
+) -> Redirect {
+    let mut bg = backend.lock().unwrap();
+    // the student keeps their copy of the answers submitted so far
+    if let Some(group) = group_id(&mut bg, num, &apikey.user) {
+        remove_member(&mut bg, group, &apikey.user);
+    }
+    drop(bg);
+
+    Redirect::to(format!("/groups/{}", num))
+}
+
+#[post("/groups/<num>", data = "<data>")]
+pub(crate) fn staff_create(
+    _adm: Admin,
+    num: LectureId,
+    data: Form<StaffGroupForm>,
+    backend: &State<Arc<Mutex<MySqlBackend>>>,
+) -> Result<Redirect, BadRequest<String>> {
+    let mut emails: Vec<&str> = vec![];
+    for e in data.members.lines().map(|l| l.trim()) {
+        if !e.is_empty() && !emails.contains(&e) {
+            emails.push(e);
+        }
+    }
+    let mut bg = backend.lock().unwrap();
+    let unknown: Vec<&str> = emails
+        .iter()
+        .cloned()
+        .filter(|e| {
+            bg.prep_exec("SELECT email FROM users WHERE email = ?", vec![(*e).into()])
+                .is_empty()
+        })
+        .collect();
+    if !unknown.is_empty() {
+        return Err(BadRequest(Some(format!(
+            "No such users: {}",
+            unknown.join(", ")
+        ))));
+    }
+    let taken: Vec<&str> = emails
+        .iter()
+        .cloned()
+        .filter(|e| group_id(&mut bg, num, e).is_some())
+        .collect();
+    if !taken.is_empty() {
+        return Err(BadRequest(Some(format!(
+            "Already in a group for this lecture: {}",
+            taken.join(", ")
+        ))));
+    }
+    // members who already answered on their own now share the most recent submission
+    let latest = emails
+        .iter()
+        .filter_map(|e| {
+            let res = bg.prep_exec(
+                "SELECT MAX(submitted_at) FROM answers WHERE lec = ? AND email = ?",
+                vec![num.into(), (*e).into()],
+            );
+            questions::opt_datetime(&res[0][0]).map(|t| (t, *e))
+        })
+        .max();
+    // staff-created groups need no invitations
+    bg.transaction(|bg| {
+        let group = create_group(bg, num, data.name.trim());
+        for email in &emails {
+            bg.insert(
+                "group_members",
+                vec![group.into(), (*email).into(), 1.into()],
+            );
+        }
+        if let Some((_, from)) = latest {
+            for email in emails.iter().filter(|e| **e != from) {
+                copy_answers(bg, num, from, email);
+            }
+        }
+    })
+    .map_err(|e| BadRequest(Some(format!("Creating the group failed: {}", e))))?;
+    drop(bg);
+
+    Ok(Redirect::to(format!("/admin/lec/{}", num)))
+}
+
+#[post("/groups/<num>/<group>/delete")]
+pub(crate) fn staff_delete(
+    _adm: Admin,
+    num: LectureId,
+    group: u64,
+    backend: &State<Arc<Mutex<MySqlBackend>>>,
+) -> Redirect {
+    let mut bg = backend.lock().unwrap();
+    bg.prep_exec(
+        "DELETE FROM group_members WHERE group_id IN \
+         (SELECT id FROM lec_groups WHERE id = ? AND lec = ?)",
+        vec![group.into(), num.into()],
+    );
+    bg.prep_exec(
+        "DELETE FROM lec_groups WHERE id = ? AND lec = ?",
+        vec![group.into(), num.into()],
+    );
+    drop(bg);
+
+    Redirect::to(format!("/admin/lec/{}", num))
+}
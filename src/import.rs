@@ -0,0 +1,704 @@
+use crate::admin::{AddLectureQuestionForm, Admin};
+use crate::backend::{MySqlBackend, Value};
+use crate::qtypes::QuestionType;
+use crate::questions::LectureId;
+use mysql::from_value;
+use rocket::form::Form;
+use rocket::State;
+use rocket_dyn_templates::Template;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+/// Names of the columns of `questions` that an import can change, in table order after the
+/// key columns.
+const QUESTION_COLUMNS: [&str; 7] = [
+    "prompt",
+    "type",
+    "options",
+    "tolerance",
+    "answer key",
+    "points",
+    "position",
+];
+
+/// A lecture and its questions, as described in an import file.
+#[derive(Debug, Deserialize)]
+pub(crate) struct LectureImport {
+    id: LectureId,
+    label: String,
+    #[serde(default)]
+    questions: Vec<QuestionImport>,
+}
+
+#[derive(Debug, Deserialize)]
+struct QuestionImport {
+    /// Defaults to the question's position in the file, starting from one
+    id: Option<u64>,
+    prompt: String,
+    #[serde(rename = "type", default = "default_type")]
+    qtype: String,
+    #[serde(default)]
+    options: Vec<String>,
+    tolerance: Option<f64>,
+    /// A string or number, or a list of the correct options
+    answer: Option<serde_json::Value>,
+    points: Option<f64>,
+}
+
+fn default_type() -> String {
+    String::from("text")
+}
+
+/// File formats accepted for import.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum ImportFormat {
+    Toml,
+    Json,
+    Markdown,
+}
+
+impl ImportFormat {
+    pub(crate) fn from_name(name: &str) -> Option<ImportFormat> {
+        match name {
+            "toml" => Some(ImportFormat::Toml),
+            "json" => Some(ImportFormat::Json),
+            "md" | "markdown" => Some(ImportFormat::Markdown),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, FromForm)]
+pub(crate) struct ImportForm {
+    format: String,
+    content: String,
+    dry_run: bool,
+}
+
+/// One change an import makes (or would make, in a dry run).
+#[derive(Debug, Serialize)]
+pub(crate) struct Change {
+    pub kind: &'static str,
+    pub what: String,
+    pub details: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct ImportContext {
+    format: String,
+    content: String,
+    dry_run: bool,
+    applied: bool,
+    errors: Vec<String>,
+    changes: Vec<Change>,
+    parent: &'static str,
+}
+
+/// Parses an import file.
+pub(crate) fn parse(format: ImportFormat, content: &str) -> Result<LectureImport, String> {
+    match format {
+        ImportFormat::Json => serde_json::from_str(content).map_err(|e| e.to_string()),
+        ImportFormat::Toml => {
+            let mut parser = toml::Parser::new(content);
+            match parser.parse() {
+                Some(table) => serde_json::from_value(toml_to_json(toml::Value::Table(table)))
+                    .map_err(|e| e.to_string()),
+                None => Err(parser
+                    .errors
+                    .iter()
+                    .map(|e| e.desc.clone())
+                    .collect::<Vec<_>>()
+                    .join("; ")),
+            }
+        }
+        ImportFormat::Markdown => parse_markdown(content),
+    }
+}
+
+fn toml_to_json(value: toml::Value) -> serde_json::Value {
+    match value {
+        toml::Value::String(s) | toml::Value::Datetime(s) => serde_json::Value::String(s),
+        toml::Value::Integer(i) => i.into(),
+        toml::Value::Float(f) => f.into(),
+        toml::Value::Boolean(b) => b.into(),
+        toml::Value::Array(a) => a.into_iter().map(toml_to_json).collect(),
+        toml::Value::Table(t) => {
+            serde_json::Value::Object(t.into_iter().map(|(k, v)| (k, toml_to_json(v))).collect())
+        }
+    }
+}
+
+/// Parses the Markdown import format:
+///
+/// ```text
+/// # 3: Lecture label
+///
+/// ## Question 1 (single, 2 points)
+/// The prompt, in Markdown.
+///
+/// - [x] a correct option
+/// - [ ] another option
+///
+/// ## Question 2 (numeric)
+/// Another prompt.
+///
+/// Answer: 42
+/// Tolerance: 0.5
+/// ```
+///
+/// Question headings give the question id (optional) and, in parentheses, its type and points.
+/// `Answer:`, `Tolerance:` and `Accept:` (file extensions for uploads) lines set the answer key
+/// and other settings; everything else is the prompt.
+fn parse_markdown(content: &str) -> Result<LectureImport, String> {
+    let mut lecture: Option<(u64, String)> = None;
+    let mut questions = vec![];
+    let mut current: Option<(QuestionImport, Vec<String>, Vec<String>)> = None;
+
+    let finish = |q: Option<(QuestionImport, Vec<String>, Vec<String>)>,
+                  questions: &mut Vec<QuestionImport>| {
+        if let Some((mut q, prompt, answers)) = q {
+            q.prompt = prompt.join("\n").trim().to_string();
+            if !answers.is_empty() {
+                q.answer = Some(answers.into_iter().map(serde_json::Value::String).collect());
+            }
+            questions.push(q);
+        }
+    };
+
+    let mut in_fence = false;
+    for (n, line) in content.lines().enumerate() {
+        // fenced code blocks (including the fence lines) are part of the prompt, even if their
+        // lines look like headings or answer keys
+        let trimmed = line.trim_start();
+        let is_fence = trimmed.starts_with("```") || trimmed.starts_with("~~~");
+        if is_fence || in_fence {
+            if is_fence {
+                in_fence = !in_fence;
+            }
+            match current.as_mut() {
+                Some((_, prompt, _)) => prompt.push(line.to_string()),
+                None => return Err(format!("line {}: text before the first question", n + 1)),
+            }
+        } else if let Some(heading) = line.strip_prefix("## ") {
+            finish(current.take(), &mut questions);
+            current = Some((parse_question_heading(heading, n + 1)?, vec![], vec![]));
+        } else if let (None, Some(heading)) = (&lecture, line.strip_prefix("# ")) {
+            let (id, label) = heading.split_once(':').ok_or_else(|| {
+                format!(
+                    "line {}: lecture heading must look like \"# 3: Label\"",
+                    n + 1
+                )
+            })?;
+            let id = id
+                .trim()
+                .trim_start_matches(|c: char| !c.is_ascii_digit())
+                .parse()
+                .map_err(|_| format!("line {}: invalid lecture id", n + 1))?;
+            lecture = Some((id, label.trim().to_string()));
+        } else if let Some((q, prompt, answers)) = current.as_mut() {
+            let qtype = QuestionType::from_name(&q.qtype);
+            let choice = qtype.map(|t| t.is_choice()).unwrap_or(false);
+            if let Some(v) = line.strip_prefix("Answer:") {
+                answers.push(v.trim().to_string());
+            } else if let Some(v) = line.strip_prefix("Tolerance:") {
+                q.tolerance = Some(
+                    v.trim()
+                        .parse()
+                        .map_err(|_| format!("line {}: invalid tolerance", n + 1))?,
+                );
+            } else if let Some(v) = line.strip_prefix("Accept:") {
+                q.options.extend(
+                    v.split(',')
+                        .map(|e| e.trim().to_string())
+                        .filter(|e| !e.is_empty()),
+                );
+            } else if let (true, Some(o)) = (choice, line.strip_prefix("- [ ] ")) {
+                q.options.push(o.trim().to_string());
+            } else if let (true, Some(o)) = (
+                choice,
+                line.strip_prefix("- [x] ")
+                    .or_else(|| line.strip_prefix("- [X] ")),
+            ) {
+                q.options.push(o.trim().to_string());
+                answers.push(o.trim().to_string());
+            } else {
+                prompt.push(line.to_string());
+            }
+        } else if !line.trim().is_empty() {
+            return Err(format!("line {}: text before the first question", n + 1));
+        }
+    }
+    finish(current.take(), &mut questions);
+
+    let (id, label) = lecture.ok_or("missing lecture heading (\"# 3: Label\")")?;
+    Ok(LectureImport {
+        id: id,
+        label: label,
+        questions: questions,
+    })
+}
+
+/// Parses a question heading such as `Question 2 (multi, 2 points)`.
+fn parse_question_heading(heading: &str, line: usize) -> Result<QuestionImport, String> {
+    let (name, attrs) = match heading.find('(') {
+        Some(i) => (
+            &heading[..i],
+            heading[i + 1..].trim_end().trim_end_matches(')'),
+        ),
+        None => (heading, ""),
+    };
+    let mut q = QuestionImport {
+        id: name.split_whitespace().last().and_then(|w| w.parse().ok()),
+        prompt: String::new(),
+        qtype: default_type(),
+        options: vec![],
+        tolerance: None,
+        answer: None,
+        points: None,
+    };
+    for attr in attrs.split(',').map(|a| a.trim()).filter(|a| !a.is_empty()) {
+        if let Some(points) = attr
+            .strip_suffix("points")
+            .or_else(|| attr.strip_suffix("point"))
+        {
+            q.points = Some(
+                points
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("line {}: invalid points \"{}\"", line, attr))?,
+            );
+        } else {
+            q.qtype = attr.to_string();
+        }
+    }
+    Ok(q)
+}
+
+/// Checks the imported questions and turns them into form-equivalent questions with ids.
+fn validate(import: &LectureImport) -> Result<Vec<AddLectureQuestionForm>, Vec<String>> {
+    let mut errors = vec![];
+    let mut ids = HashSet::new();
+    let mut forms = vec![];
+    for (i, q) in import.questions.iter().enumerate() {
+        let id = q.id.unwrap_or(i as u64 + 1);
+        let err = |e: String| format!("Question {}: {}", id, e);
+        if !ids.insert(id) {
+            errors.push(err("duplicate question id".into()));
+        }
+        let qtype = match QuestionType::from_name(&q.qtype) {
+            Some(t) => t,
+            None => {
+                errors.push(err(format!("unknown question type \"{}\"", q.qtype)));
+                continue;
+            }
+        };
+        let answer: Vec<String> = match &q.answer {
+            None => vec![],
+            Some(serde_json::Value::String(s)) => vec![s.clone()],
+            Some(serde_json::Value::Array(a)) => a
+                .iter()
+                .map(|v| match v {
+                    serde_json::Value::String(s) => s.clone(),
+                    v => v.to_string(),
+                })
+                .collect(),
+            Some(v) => vec![v.to_string()],
+        };
+        if qtype.is_choice() {
+            if q.options.is_empty() {
+                errors.push(err("multiple-choice questions need options".into()));
+            }
+            for a in &answer {
+                if !q.options.contains(a) {
+                    errors.push(err(format!("answer \"{}\" is not one of the options", a)));
+                }
+            }
+            if qtype == QuestionType::SingleChoice && answer.len() > 1 {
+                errors.push(err("single-choice questions have one answer".into()));
+            }
+        }
+        if qtype == QuestionType::Numeric {
+            for a in &answer {
+                if a.trim().parse::<f64>().is_err() {
+                    errors.push(err(format!("answer \"{}\" is not a number", a)));
+                }
+            }
+        }
+        forms.push(AddLectureQuestionForm::new(
+            id,
+            q.prompt.clone(),
+            qtype.as_str().to_string(),
+            q.options.join("\n"),
+            q.tolerance,
+            answer.join("\n"),
+            q.points,
+        ));
+    }
+    if errors.is_empty() {
+        Ok(forms)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Works out what importing a lecture changes, without changing anything.
+fn plan(
+    bg: &mut MySqlBackend,
+    import: &LectureImport,
+    questions: &[AddLectureQuestionForm],
+) -> Vec<Change> {
+    let mut changes = vec![];
+    let lres = bg.prep_exec(
+        "SELECT label FROM lectures WHERE id = ?",
+        vec![import.id.into()],
+    );
+    match lres.get(0) {
+        None => changes.push(Change {
+            kind: "add",
+            what: format!("lecture {}", import.id),
+            details: vec![format!("label: {}", import.label)],
+        }),
+        Some(r) => {
+            let label: String = from_value(r[0].clone());
+            changes.push(Change {
+                kind: if label == import.label {
+                    "unchanged"
+                } else {
+                    "update"
+                },
+                what: format!("lecture {}", import.id),
+                details: if label == import.label {
+                    vec![]
+                } else {
+                    vec![format!("label: {} -> {}", label, import.label)]
+                },
+            });
+        }
+    }
+
+    let mut existing: BTreeMap<u64, Vec<Value>> = bg
+        .prep_exec(
+            "SELECT * FROM questions WHERE lec = ?",
+            vec![import.id.into()],
+        )
+        .into_iter()
+        .map(|r| (from_value(r[1].clone()), r))
+        .collect();
+    for row in question_rows(bg, import, questions) {
+        let id: u64 = from_value(row[1].clone());
+        let what = format!("question {}", id);
+        match existing.remove(&id) {
+            None => changes.push(Change {
+                kind: "add",
+                what: what,
+                details: vec![],
+            }),
+            Some(old) => {
+                let changed: Vec<String> = QUESTION_COLUMNS
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| !same_value(&old[i + 2], &row[i + 2]))
+                    .map(|(_, c)| c.to_string())
+                    .collect();
+                changes.push(Change {
+                    kind: if changed.is_empty() {
+                        "unchanged"
+                    } else {
+                        "update"
+                    },
+                    what: what,
+                    details: changed,
+                });
+            }
+        }
+    }
+    // imports never delete questions
+    for id in existing.keys() {
+        changes.push(Change {
+            kind: "kept",
+            what: format!("question {}", id),
+            details: vec![String::from("not in the import file")],
+        });
+    }
+    changes
+}
+
+/// The `questions` rows of the imported questions, ordered as in the file. Questions that the
+/// import keeps because they are not in the file stay first, so the imported questions are
+/// positioned after them instead of sharing their positions.
+fn question_rows(
+    bg: &mut MySqlBackend,
+    import: &LectureImport,
+    questions: &[AddLectureQuestionForm],
+) -> Vec<Vec<Value>> {
+    let imported: HashSet<u64> = questions
+        .iter()
+        .map(|q| from_value(q.row(import.id, 0)[1].clone()))
+        .collect();
+    let first = bg
+        .prep_exec(
+            "SELECT q, COALESCE(position, q) FROM questions WHERE lec = ?",
+            vec![import.id.into()],
+        )
+        .into_iter()
+        .filter(|r| !imported.contains(&from_value::<u64>(r[0].clone())))
+        .map(|r| from_value::<u64>(r[1].clone()))
+        .max()
+        .map_or(1, |p| p + 1);
+    questions
+        .iter()
+        .enumerate()
+        .map(|(i, q)| q.row(import.id, first + i as u64))
+        .collect()
+}
+
+/// Compares a value read from the database with one about to be written.
+fn same_value(old: &Value, new: &Value) -> bool {
+    match (old, new) {
+        (a, b) if a == b => true,
+        (Value::NULL, _) | (_, Value::NULL) => false,
+        (Value::Double(a), Value::Double(b)) => a == b,
+        (Value::Float(a), Value::Double(b)) => (*a as f64) == *b,
+        (Value::Int(a), Value::UInt(b)) => *a >= 0 && *a as u64 == *b,
+        (old, new) => from_value::<String>(old.clone()) == from_value::<String>(new.clone()),
+    }
+}
+
+/// Imports a lecture and its questions in a single transaction. With `dry_run`, only reports
+/// what would change.
+pub(crate) fn run(
+    bg: &mut MySqlBackend,
+    import: &LectureImport,
+    dry_run: bool,
+) -> Result<Vec<Change>, Vec<String>> {
+    let questions = validate(import)?;
+    let changes = plan(bg, import, &questions);
+    if dry_run {
+        return Ok(changes);
+    }
+    let rows = question_rows(bg, import, &questions);
+
+    bg.transaction(|bg| {
+        let exists = !bg
+            .prep_exec(
+                "SELECT id FROM lectures WHERE id = ?",
+                vec![import.id.into()],
+            )
+            .is_empty();
+        if exists {
+            bg.prep_exec(
+                "UPDATE lectures SET label = ? WHERE id = ?",
+                vec![import.label.clone().into(), import.id.into()],
+            );
+        } else {
+            bg.insert(
+                "lectures",
+                vec![
+                    import.id.into(),
+                    import.label.clone().into(),
+                    0.into(),
+                    0.into(),
+                    Value::NULL,
+                ],
+            );
+        }
+        for row in rows {
+            bg.replace("questions", row);
+        }
+        changes
+    })
+    .map_err(|e| vec![format!("Import failed, nothing was changed: {}", e)])
+}
+
+/// Imports a lecture from a file, for the `import` command. The format is taken from the file
+/// extension unless given.
+pub(crate) fn import_file(
+    bg: &mut MySqlBackend,
+    path: &str,
+    format: Option<&str>,
+    dry_run: bool,
+) -> Result<Vec<Change>, Vec<String>> {
+    let name = format
+        .or_else(|| {
+            std::path::Path::new(path)
+                .extension()
+                .and_then(|e| e.to_str())
+        })
+        .unwrap_or("");
+    let format =
+        ImportFormat::from_name(name).ok_or_else(|| vec![format!("Unknown format: {}", name)])?;
+    let content = std::fs::read_to_string(path).map_err(|e| vec![format!("{}: {}", path, e)])?;
+    let import = parse(format, &content).map_err(|e| vec![e])?;
+    run(bg, &import, dry_run)
+}
+
+#[get("/")]
+pub(crate) fn import(_adm: Admin) -> Template {
+    let ctx = ImportContext {
+        format: String::from("toml"),
+        content: String::new(),
+        dry_run: true,
+        applied: false,
+        errors: vec![],
+        changes: vec![],
+        parent: "layout",
+    };
+    Template::render("admin/import", &ctx)
+}
+
+#[post("/", data = "<data>")]
+pub(crate) fn import_submit(
+    _adm: Admin,
+    data: Form<ImportForm>,
+    backend: &State<Arc<Mutex<MySqlBackend>>>,
+) -> Template {
+    let res = ImportFormat::from_name(&data.format)
+        .ok_or_else(|| vec![format!("Unknown format: {}", data.format)])
+        .and_then(|format| parse(format, &data.content).map_err(|e| vec![e]))
+        .and_then(|import| {
+            let mut bg = backend.lock().unwrap();
+            run(&mut bg, &import, data.dry_run)
+        });
+
+    let (changes, errors) = match res {
+        Ok(changes) => (changes, vec![]),
+        Err(errors) => (vec![], errors),
+    };
+    let ctx = ImportContext {
+        format: data.format.clone(),
+        content: data.content.clone(),
+        dry_run: data.dry_run,
+        applied: !data.dry_run && errors.is_empty(),
+        errors: errors,
+        changes: changes,
+        parent: "layout",
+    };
+    Template::render("admin/import", &ctx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_markdown_questions() {
+        let import = parse(
+            ImportFormat::Markdown,
+            "# Lecture 3: Consistency\n\
+             \n\
+             ## Question 1 (single, 2 points)\n\
+             Which is *strongest*?\n\
+             \n\
+             - [ ] eventual\n\
+             - [x] linearizable\n\
+             \n\
+             ## Question 4 (numeric)\n\
+             How many replicas?\n\
+             Answer: 3\n\
+             Tolerance: 0.5\n",
+        )
+        .unwrap();
+        assert_eq!(import.id, 3);
+        assert_eq!(import.label, "Consistency");
+        assert_eq!(import.questions.len(), 2);
+
+        let q = &import.questions[0];
+        assert_eq!(q.id, Some(1));
+        assert_eq!(q.qtype, "single");
+        assert_eq!(q.points, Some(2.0));
+        assert_eq!(q.prompt, "Which is *strongest*?");
+        assert_eq!(q.options, vec!["eventual", "linearizable"]);
+        assert_eq!(q.answer, Some(serde_json::json!(["linearizable"])));
+
+        let q = &import.questions[1];
+        assert_eq!(q.id, Some(4));
+        assert_eq!(q.qtype, "numeric");
+        assert_eq!(q.prompt, "How many replicas?");
+        assert_eq!(q.answer, Some(serde_json::json!(["3"])));
+        assert_eq!(q.tolerance, Some(0.5));
+    }
+
+    #[test]
+    fn parse_markdown_keeps_fenced_code_in_the_prompt() {
+        let import = parse(
+            ImportFormat::Markdown,
+            "# 1: Shell\n\
+             ## Question 1\n\
+             What does this print?\n\
+             ```\n\
+             ## not a heading\n\
+             Answer: not a key\n\
+             ```\n\
+             Answer: hi\n",
+        )
+        .unwrap();
+        assert_eq!(import.questions.len(), 1);
+        assert_eq!(
+            import.questions[0].prompt,
+            "What does this print?\n```\n## not a heading\nAnswer: not a key\n```"
+        );
+        assert_eq!(import.questions[0].answer, Some(serde_json::json!(["hi"])));
+    }
+
+    #[test]
+    fn parse_markdown_errors() {
+        assert!(parse(ImportFormat::Markdown, "## Question 1\nprompt\n").is_err());
+        assert!(parse(ImportFormat::Markdown, "# 1: L\nstray text\n").is_err());
+        assert!(parse(ImportFormat::Markdown, "# 1: L\n```\ncode\n```\n").is_err());
+        assert!(parse(ImportFormat::Markdown, "# L\n## Question 1\n").is_err());
+        assert!(parse(ImportFormat::Markdown, "# 1: L\n## Q (x points)\n").is_err());
+    }
+
+    #[test]
+    fn parse_toml() {
+        let import = parse(
+            ImportFormat::Toml,
+            "id = 2\n\
+             label = \"Logging\"\n\
+             \n\
+             [[questions]]\n\
+             prompt = \"Why log?\"\n\
+             \n\
+             [[questions]]\n\
+             id = 5\n\
+             type = \"multi\"\n\
+             prompt = \"Pick two\"\n\
+             options = [\"a\", \"b\", \"c\"]\n\
+             answer = [\"a\", \"c\"]\n\
+             points = 1.5\n",
+        )
+        .unwrap();
+        assert_eq!(import.id, 2);
+        assert_eq!(import.label, "Logging");
+        assert_eq!(import.questions.len(), 2);
+        assert_eq!(import.questions[0].id, None);
+        assert_eq!(import.questions[0].qtype, "text");
+        assert_eq!(import.questions[1].id, Some(5));
+        assert_eq!(import.questions[1].options, vec!["a", "b", "c"]);
+        assert_eq!(
+            import.questions[1].answer,
+            Some(serde_json::json!(["a", "c"]))
+        );
+        assert_eq!(import.questions[1].points, Some(1.5));
+
+        assert!(parse(ImportFormat::Toml, "id = \n").is_err());
+    }
+
+    #[test]
+    fn parse_json() {
+        let import = parse(
+            ImportFormat::Json,
+            r#"{"id": 7, "label": "Caching", "questions": [
+                {"prompt": "TTL?", "type": "numeric", "answer": 60, "tolerance": 1}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(import.id, 7);
+        assert_eq!(import.questions.len(), 1);
+        assert_eq!(import.questions[0].qtype, "numeric");
+        assert_eq!(import.questions[0].answer, Some(serde_json::json!(60)));
+        assert_eq!(import.questions[0].tolerance, Some(1.0));
+
+        assert!(parse(ImportFormat::Json, r#"{"label": "no id"}"#).is_err());
+    }
+}
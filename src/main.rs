@@ -14,10 +14,28 @@ mod admin;
 mod apikey;
 mod args;
 mod backend;
+mod clone;
 mod config;
+mod csv;
 mod email;
+mod exports;
+mod gradebook;
+mod grading;
+mod groups;
+mod import;
 mod login;
+mod markdown;
+mod notifications;
+mod outbox;
+mod peer;
+mod qtypes;
 mod questions;
+mod receipts;
+mod regrades;
+mod roster;
+mod rubric;
+mod stats;
+mod uploads;
 
 use backend::MySqlBackend;
 use rocket::fs::FileServer;
@@ -53,6 +71,96 @@ async fn main() {
     let args = args::parse_args();
     let config = args.config;
 
+    if let Some(command) = args.command {
+        // commands work on the existing database, so never prime it
+        let mut bg = MySqlBackend::new(
+            &config.db_user,
+            &config.db_password,
+            &format!("{}", args.class),
+            Some(new_logger()),
+            false,
+        )
+        .unwrap();
+        match command {
+            args::Command::Import {
+                file,
+                format,
+                dry_run,
+            } => match import::import_file(&mut bg, &file, format.as_deref(), dry_run) {
+                Ok(changes) => {
+                    for c in changes {
+                        if c.details.is_empty() {
+                            println!("{}: {}", c.kind, c.what);
+                        } else {
+                            println!("{}: {} ({})", c.kind, c.what, c.details.join(", "));
+                        }
+                    }
+                    if dry_run {
+                        println!("Dry run, nothing was changed.");
+                    }
+                }
+                Err(errors) => {
+                    for e in errors {
+                        eprintln!("{}", e);
+                    }
+                    std::process::exit(1);
+                }
+            },
+            args::Command::Roster {
+                file,
+                create_users,
+                send_keys,
+                dry_run,
+            } => {
+                match roster::import_file(&mut bg, &config, &file, create_users, send_keys, dry_run)
+                {
+                    Ok(report) => {
+                        for e in &report.added {
+                            println!("added: {}", e);
+                        }
+                        for e in &report.removed {
+                            println!("removed: {}", e);
+                        }
+                        println!("unchanged: {} students", report.unchanged.len());
+                        for e in &report.created_users {
+                            println!("API key created: {}", e);
+                        }
+                        for e in &report.emailed {
+                            println!("API key email queued: {}", e);
+                        }
+                        for e in &report.errors {
+                            eprintln!("{}", e);
+                        }
+                        if dry_run {
+                            println!("Dry run, nothing was changed.");
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            args::Command::CloneTerm { from, dry_run } => {
+                let mut source = MySqlBackend::new(
+                    &config.db_user,
+                    &config.db_password,
+                    &from,
+                    Some(new_logger()),
+                    false,
+                )
+                .unwrap();
+                for line in clone::clone_term(&mut source, &mut bg, dry_run) {
+                    println!("{}", line);
+                }
+                if dry_run {
+                    println!("Dry run, nothing was changed.");
+                }
+            }
+        }
+        return;
+    }
+
     let backend = Arc::new(Mutex::new(
         MySqlBackend::new(
             &config.db_user,
@@ -64,6 +172,11 @@ async fn main() {
         .unwrap(),
     ));
 
+    if config.send_emails {
+        notifications::start_digest_worker(backend.clone(), config.clone());
+        outbox::start_worker(backend.clone(), config.clone());
+    }
+
     let template_dir = config.template_dir.clone();
     let resource_dir = config.resource_dir.clone();
 
@@ -72,9 +185,17 @@ async fn main() {
             .handlebars
             .register_templates_directory(".hbs", std::path::Path::new(&template_dir))
             .expect("failed to set template path!");
+        engines
+            .handlebars
+            .register_helper("markdown", Box::new(markdown::helper));
     });
 
-    if let Err(e) = rocket::build()
+    // Rocket enforces the upload size while receiving the form; allow a few uploads per form
+    let figment = rocket::Config::figment()
+        .merge(("limits.file", config.upload_max_size))
+        .merge(("limits.data-form", 4 * config.upload_max_size));
+
+    if let Err(e) = rocket::custom(figment)
         .attach(template)
         .manage(backend)
         .manage(config)
@@ -87,17 +208,84 @@ async fn main() {
         )
         .mount("/apikey/check", routes![apikey::check])
         .mount("/apikey/generate", routes![apikey::generate])
-        .mount("/answers", routes![questions::answers])
+        .mount(
+            "/answers",
+            routes![
+                questions::answers,
+                uploads::download,
+                exports::lecture_csv,
+                exports::lecture_json,
+                exports::course_csv,
+                exports::course_json
+            ],
+        )
         .mount("/leclist", routes![questions::leclist])
+        .mount(
+            "/groups",
+            routes![
+                groups::groups,
+                groups::create,
+                groups::invite,
+                groups::accept,
+                groups::decline,
+                groups::leave
+            ],
+        )
+        .mount("/regrades", routes![regrades::regrade, regrades::request])
+        .mount("/reviews", routes![peer::reviews, peer::reviews_submit])
+        .mount(
+            "/grade",
+            routes![grading::grade_start, grading::grade, grading::grade_submit],
+        )
         .mount("/login", routes![login::login])
         .mount(
             "/admin/lec/add",
             routes![admin::lec_add, admin::lec_add_submit],
         )
-        .mount("/admin/users", routes![admin::get_registered_users])
+        .mount(
+            "/admin/users",
+            routes![admin::get_registered_users, admin::user_progress],
+        )
+        .mount(
+            "/admin/import",
+            routes![import::import, import::import_submit],
+        )
+        .mount(
+            "/admin/roster",
+            routes![roster::roster, roster::roster_submit],
+        )
+        .mount("/admin/gradebook", routes![gradebook::export])
+        .mount("/admin/stats", routes![stats::stats, stats::stats_json])
+        .mount(
+            "/admin/regrades",
+            routes![regrades::queue, regrades::resolve],
+        )
         .mount(
             "/admin/lec",
-            routes![admin::lec, admin::addq, admin::editq, admin::editq_submit],
+            routes![
+                admin::lec,
+                admin::addq,
+                admin::editq,
+                admin::editq_submit,
+                admin::release_grades,
+                admin::blind_grading,
+                admin::set_deadline,
+                notifications::set,
+                clone::clone_lecture,
+                admin::rename,
+                admin::reorder,
+                admin::delete_lec,
+                admin::delete_lec_submit,
+                admin::delete_question,
+                admin::delete_question_submit,
+                peer::assign_reviews,
+                groups::staff_create,
+                groups::staff_delete,
+                grading::deanonymize,
+                rubric::add_item,
+                rubric::edit_item,
+                rubric::delete_item
+            ],
         )
         .launch()
         .await
@@ -0,0 +1,206 @@
+use pulldown_cmark::{html, Options, Parser};
+use rocket_dyn_templates::handlebars::{
+    Context, Handlebars, Helper, HelperResult, Output, RenderContext,
+};
+
+/// Delimits the placeholders that stand in for math while the Markdown is rendered. These are
+/// Unicode private-use characters, which neither the Markdown renderer nor the sanitizer touch.
+const MATH_START: char = '\u{E000}';
+const MATH_END: char = '\u{E001}';
+
+/// Renders user-provided Markdown (prompts and answers) to sanitized HTML. Math delimited by
+/// `$...$` or `$$...$$` is passed through untouched for KaTeX to typeset in the browser.
+pub(crate) fn render(text: &str) -> String {
+    let (text, math) = extract_math(text);
+
+    let mut unsafe_html = String::new();
+    let parser = Parser::new_ext(
+        &text,
+        Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH,
+    );
+    html::push_html(&mut unsafe_html, parser);
+    let safe_html = ammonia::clean(&unsafe_html);
+
+    restore_math(&safe_html, &math)
+}
+
+/// Handlebars helper for `render`, used as `{{{ markdown this.prompt }}}`.
+pub(crate) fn helper(
+    h: &Helper<'_, '_>,
+    _: &Handlebars<'_>,
+    _: &Context,
+    _: &mut RenderContext<'_, '_>,
+    out: &mut dyn Output,
+) -> HelperResult {
+    let text = h.param(0).and_then(|v| v.value().as_str()).unwrap_or("");
+    out.write(&render(text))?;
+    Ok(())
+}
+
+/// Replaces math outside of code with numbered placeholders, so that Markdown syntax inside
+/// math (e.g., `*` or `_`) is left alone. Returns the rewritten text and the math spans (as
+/// `(display, tex)` pairs) in placeholder order. Any placeholder delimiters already in the text
+/// are dropped, so that only the placeholders made here are restored.
+fn extract_math(text: &str) -> (String, Vec<(bool, String)>) {
+    let text = text.replace(|c: char| c == MATH_START || c == MATH_END, "");
+    let mut out = String::with_capacity(text.len());
+    let mut math = vec![];
+    let mut in_fence = false;
+    let mut rest = text.as_str();
+    while !rest.is_empty() {
+        // copy fenced code blocks (including the fence lines) verbatim
+        let at_line_start = out.is_empty() || out.ends_with('\n');
+        let trimmed = rest.trim_start_matches(|c: char| c == ' ' || c == '\t');
+        let is_fence = at_line_start && (trimmed.starts_with("```") || trimmed.starts_with("~~~"));
+        if is_fence {
+            in_fence = !in_fence;
+        }
+        if is_fence || in_fence {
+            let end = rest.find('\n').map(|i| i + 1).unwrap_or(rest.len());
+            out.push_str(&rest[..end]);
+            rest = &rest[end..];
+            continue;
+        }
+
+        let c = rest.chars().next().unwrap();
+        if c == '\\' && rest[1..].starts_with('$') {
+            out.push_str("\\$");
+            rest = &rest[2..];
+        } else if c == '`' {
+            // inline code: copy everything up to the matching run of backticks
+            let ticks = rest.len() - rest.trim_start_matches('`').len();
+            let fence = &rest[..ticks];
+            match rest[ticks..].find(fence) {
+                Some(i) => {
+                    let end = ticks + i + ticks;
+                    out.push_str(&rest[..end]);
+                    rest = &rest[end..];
+                }
+                None => {
+                    out.push_str(fence);
+                    rest = &rest[ticks..];
+                }
+            }
+        } else if rest.starts_with("$$") {
+            match rest[2..].find("$$") {
+                Some(i) => {
+                    push_placeholder(&mut out, math.len());
+                    math.push((true, rest[2..2 + i].to_string()));
+                    rest = &rest[2 + i + 2..];
+                }
+                None => {
+                    out.push_str("$$");
+                    rest = &rest[2..];
+                }
+            }
+        } else if c == '$' {
+            match rest[1..].find(|c: char| c == '$' || c == '\n') {
+                Some(i) if rest[1 + i..].starts_with('$') && i > 0 => {
+                    push_placeholder(&mut out, math.len());
+                    math.push((false, rest[1..1 + i].to_string()));
+                    rest = &rest[1 + i + 1..];
+                }
+                _ => {
+                    out.push('$');
+                    rest = &rest[1..];
+                }
+            }
+        } else {
+            out.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+
+    (out, math)
+}
+
+fn push_placeholder(out: &mut String, n: usize) {
+    out.push(MATH_START);
+    out.push_str(&n.to_string());
+    out.push(MATH_END);
+}
+
+/// Puts the (HTML-escaped) math back in place of the placeholders.
+fn restore_math(html: &str, math: &[(bool, String)]) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find(MATH_START) {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + MATH_START.len_utf8()..];
+        let end = match after.find(MATH_END) {
+            Some(end) => end,
+            None => {
+                rest = after;
+                break;
+            }
+        };
+        match after[..end].parse::<usize>().ok().and_then(|n| math.get(n)) {
+            Some((true, tex)) => out.push_str(&format!(
+                "<span class=\"math\">\\[{}\\]</span>",
+                escape(tex)
+            )),
+            Some((false, tex)) => out.push_str(&format!(
+                "<span class=\"math\">\\({}\\)</span>",
+                escape(tex)
+            )),
+            None => (),
+        }
+        rest = &after[end + MATH_END.len_utf8()..];
+    }
+    out.push_str(rest);
+    out
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extract_math_replaces_inline_and_display_math() {
+        let (text, math) = extract_math("Let $a*b$ and $$x_1 + x_2$$ be.");
+        assert_eq!(text, "Let \u{E000}0\u{E001} and \u{E000}1\u{E001} be.");
+        assert_eq!(
+            math,
+            vec![(false, "a*b".to_string()), (true, "x_1 + x_2".to_string())]
+        );
+    }
+
+    #[test]
+    fn extract_math_leaves_code_and_escapes_alone() {
+        let text = "costs \\$5, `$x$` and\n```\n$y$\n```\nand $ alone";
+        let (out, math) = extract_math(text);
+        assert_eq!(out, text);
+        assert!(math.is_empty());
+    }
+
+    #[test]
+    fn extract_math_drops_placeholder_characters_from_input() {
+        let (text, math) = extract_math("\u{E000}0\u{E001} and $x$");
+        assert_eq!(text, "0 and \u{E000}0\u{E001}");
+        assert_eq!(math, vec![(false, "x".to_string())]);
+    }
+
+    #[test]
+    fn restore_math_escapes_tex() {
+        let math = vec![(false, "a<b".to_string()), (true, "x".to_string())];
+        assert_eq!(
+            restore_math("<p>\u{E000}0\u{E001} \u{E000}1\u{E001}</p>", &math),
+            "<p><span class=\"math\">\\(a&lt;b\\)</span> <span class=\"math\">\\[x\\]</span></p>"
+        );
+    }
+
+    #[test]
+    fn render_keeps_markdown_out_of_math() {
+        assert_eq!(
+            render("*hi* $a*b*c$"),
+            "<p><em>hi</em> <span class=\"math\">\\(a*b*c\\)</span></p>\n"
+        );
+    }
+}
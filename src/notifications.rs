@@ -0,0 +1,321 @@
+use crate::admin::Admin;
+use crate::backend::{MySqlBackend, Value};
+use crate::config::Config;
+use crate::outbox;
+use crate::questions::{self, LectureId, QuestionId};
+use chrono::{Duration as ChronoDuration, Local};
+use mysql::from_value;
+use rocket::form::Form;
+use rocket::response::status::BadRequest;
+use rocket::response::Redirect;
+use rocket::State;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How often the digest worker looks for digests that are due.
+const DIGEST_POLL_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Recipient entries that stand for the addresses listed in the config.
+const STAFF: &str = "staff";
+const ADMINS: &str = "admins";
+
+/// How submission notifications for a lecture are delivered.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Mode {
+    /// One email per submission
+    Immediate,
+    /// Submissions are collected and sent as one email per lecture periodically
+    Digest,
+}
+
+impl Mode {
+    fn from_name(name: &str) -> Option<Mode> {
+        match name {
+            "immediate" => Some(Mode::Immediate),
+            "digest" => Some(Mode::Digest),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Mode::Immediate => "immediate",
+            Mode::Digest => "digest",
+        }
+    }
+}
+
+/// A lecture's notification settings.
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct Settings {
+    pub enabled: bool,
+    pub mode: &'static str,
+    /// Email addresses, or "staff" or "admins" for the addresses in the config
+    pub recipients: Vec<String>,
+}
+
+impl Settings {
+    /// Lectures without settings notify of every submission whoever was notified before the
+    /// settings existed: admins for lectures 90 and up, course staff for the others.
+    fn unset(num: LectureId) -> Self {
+        Settings {
+            enabled: true,
+            mode: Mode::Immediate.as_str(),
+            recipients: vec![String::from(if num >= 90 { ADMINS } else { STAFF })],
+        }
+    }
+}
+
+/// A submitted answer, as shown in notification emails.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct SubmittedAnswer {
+    pub q: QuestionId,
+    pub answer: String,
+}
+
+#[derive(Serialize)]
+struct SubmissionEmail<'a> {
+    class: &'a str,
+    lec: LectureId,
+    user: &'a str,
+    answers: &'a [SubmittedAnswer],
+}
+
+#[derive(Serialize)]
+struct DigestEntry {
+    user: String,
+    submitted_at: String,
+    answers: Vec<SubmittedAnswer>,
+}
+
+#[derive(Serialize)]
+struct DigestEmail<'a> {
+    class: &'a str,
+    lec: LectureId,
+    count: usize,
+    submissions: Vec<DigestEntry>,
+}
+
+#[derive(Debug, FromForm)]
+pub(crate) struct NotificationForm {
+    enabled: bool,
+    mode: String,
+    /// One email address (or "staff" or "admins") per line
+    recipients: String,
+}
+
+/// The notification settings of a lecture.
+pub(crate) fn settings(bg: &mut MySqlBackend, num: LectureId) -> Settings {
+    let res = bg.prep_exec(
+        "SELECT enabled, mode, recipients FROM lec_notifications WHERE lec = ?",
+        vec![num.into()],
+    );
+    match res.get(0) {
+        Some(r) => Settings {
+            enabled: from_value::<u64>(r[0].clone()) != 0,
+            mode: Mode::from_name(&from_value::<String>(r[1].clone()))
+                .unwrap_or(Mode::Immediate)
+                .as_str(),
+            recipients: from_value::<String>(r[2].clone())
+                .lines()
+                .map(String::from)
+                .collect(),
+        },
+        None => Settings::unset(num),
+    }
+}
+
+/// Stores a lecture's notification settings, e.g., when copying a lecture.
+pub(crate) fn store(bg: &mut MySqlBackend, num: LectureId, settings: &Settings) {
+    bg.replace(
+        "lec_notifications",
+        vec![
+            num.into(),
+            (settings.enabled as u64).into(),
+            settings.mode.into(),
+            settings.recipients.join("\n").into(),
+        ],
+    );
+}
+
+/// Expands "staff" and "admins" into the addresses from the config.
+fn recipients(config: &Config, settings: &Settings) -> Vec<String> {
+    let mut addrs = vec![];
+    for r in &settings.recipients {
+        let expanded = match r.as_str() {
+            STAFF => config.staff.clone(),
+            ADMINS => config.admins.clone(),
+            addr => vec![addr.to_string()],
+        };
+        for addr in expanded {
+            if !addrs.contains(&addr) {
+                addrs.push(addr);
+            }
+        }
+    }
+    addrs
+}
+
+/// Notifies the lecture's recipients of a submission, immediately or in the next digest.
+pub(crate) fn submitted(
+    bg: &mut MySqlBackend,
+    config: &Config,
+    num: LectureId,
+    user: &str,
+    answers: &[SubmittedAnswer],
+) {
+    let settings = settings(bg, num);
+    if !config.send_emails || !settings.enabled {
+        return;
+    }
+    let recipients = recipients(config, &settings);
+    if recipients.is_empty() {
+        return;
+    }
+
+    match Mode::from_name(settings.mode) {
+        Some(Mode::Digest) => {
+            bg.insert(
+                "notification_digest",
+                vec![
+                    Value::NULL,
+                    num.into(),
+                    user.into(),
+                    Local::now().naive_local().into(),
+                    serde_json::to_string(answers)
+                        .expect("failed to serialize answers")
+                        .into(),
+                ],
+            );
+        }
+        _ => outbox::enqueue(
+            bg,
+            recipients,
+            Some(user),
+            "submission",
+            &SubmissionEmail {
+                class: &config.class,
+                lec: num,
+                user: user,
+                answers: answers,
+            },
+        ),
+    }
+}
+
+/// Queues one email per lecture summarizing the submissions collected since the last digest,
+/// for lectures whose oldest collected submission is at least `config.digest_hours` old.
+pub(crate) fn send_digests(bg: &mut MySqlBackend, config: &Config) {
+    let res = bg.prep_exec(
+        "SELECT id, lec, email, submitted_at, answers FROM notification_digest ORDER BY id",
+        vec![],
+    );
+    let mut pending: BTreeMap<LectureId, Vec<Vec<Value>>> = BTreeMap::new();
+    for r in res {
+        pending.entry(from_value(r[1].clone())).or_default().push(r);
+    }
+
+    let due = Local::now().naive_local() - ChronoDuration::hours(config.digest_hours as i64);
+    for (num, rows) in pending {
+        let settings = settings(bg, num);
+        let recipients = recipients(config, &settings);
+        // settings may have changed since the submissions were queued
+        let send = settings.enabled && !recipients.is_empty();
+        // rows are ordered by id, so the first one is the oldest
+        if send && questions::opt_datetime(&rows[0][3]).map_or(false, |t| t > due) {
+            continue;
+        }
+        if send {
+            // a row that fails to parse is left out of the digest and deleted with the rest
+            let submissions = rows
+                .iter()
+                .filter_map(|r| {
+                    let answers = match serde_json::from_str(&from_value::<String>(r[4].clone())) {
+                        Ok(answers) => answers,
+                        Err(e) => {
+                            warn!(
+                                bg.log,
+                                "skipping queued digest entry {} for lecture {}: {}",
+                                from_value::<u64>(r[0].clone()),
+                                num,
+                                e
+                            );
+                            return None;
+                        }
+                    };
+                    Some(DigestEntry {
+                        user: from_value(r[2].clone()),
+                        submitted_at: questions::opt_datetime(&r[3])
+                            .map(|t| t.to_string())
+                            .unwrap_or_default(),
+                        answers: answers,
+                    })
+                })
+                .collect();
+            outbox::enqueue(
+                bg,
+                recipients,
+                None,
+                "digest",
+                &DigestEmail {
+                    class: &config.class,
+                    lec: num,
+                    count: submissions.len(),
+                    submissions: submissions,
+                },
+            );
+        }
+        for r in rows {
+            bg.prep_exec(
+                "DELETE FROM notification_digest WHERE id = ?",
+                vec![r[0].clone()],
+            );
+        }
+    }
+}
+
+/// Starts a thread that sends digests as they become due. Whether a digest is due only depends
+/// on the queued submissions, so restarting the server neither delays nor skips digests.
+pub(crate) fn start_digest_worker(backend: Arc<Mutex<MySqlBackend>>, config: Config) {
+    std::thread::spawn(move || loop {
+        let mut bg = backend.lock().unwrap();
+        send_digests(&mut bg, &config);
+        drop(bg);
+        std::thread::sleep(DIGEST_POLL_INTERVAL);
+    });
+}
+
+#[post("/notify/<num>", data = "<data>")]
+pub(crate) fn set(
+    _adm: Admin,
+    num: LectureId,
+    data: Form<NotificationForm>,
+    backend: &State<Arc<Mutex<MySqlBackend>>>,
+) -> Result<Redirect, BadRequest<String>> {
+    let mode = Mode::from_name(&data.mode)
+        .ok_or_else(|| BadRequest(Some(format!("Unknown delivery mode: {}", data.mode))))?;
+    let recipients: Vec<String> = data
+        .recipients
+        .lines()
+        .map(|r| r.trim().to_string())
+        .filter(|r| !r.is_empty())
+        .collect();
+    if let Some(r) = recipients
+        .iter()
+        .find(|r| *r != STAFF && *r != ADMINS && !r.contains('@'))
+    {
+        return Err(BadRequest(Some(format!("Invalid recipient: {}", r))));
+    }
+
+    let settings = Settings {
+        enabled: data.enabled,
+        mode: mode.as_str(),
+        recipients: recipients,
+    };
+    let mut bg = backend.lock().unwrap();
+    store(&mut bg, num, &settings);
+    drop(bg);
+
+    Ok(Redirect::to(format!("/admin/lec/{}", num)))
+}
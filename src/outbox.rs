@@ -0,0 +1,192 @@
+use crate::backend::{MySqlBackend, Value};
+use crate::config::Config;
+use crate::email::{self, EmailError, Templates};
+use chrono::{Duration as ChronoDuration, Local};
+use mysql::from_value_opt;
+use mysql::prelude::FromValue;
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How often the worker looks for emails that are due.
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+/// Emails that failed this many times are given up on (and stay in the outbox for inspection).
+const MAX_ATTEMPTS: u64 = 10;
+/// Delay before the first retry; it doubles with every further failure.
+const BASE_BACKOFF_SECS: i64 = 60;
+/// Upper bound on the delay between retries.
+const MAX_BACKOFF_SECS: i64 = 6 * 60 * 60;
+
+/// Queues the email rendered from template `template` with `context` for the background
+/// worker to send. Sending never happens on the request path, so a slow or unavailable mail
+/// server cannot fail or block a request. Replies go to `reply_to`, if given. An email that
+/// cannot be queued is logged and dropped.
+pub(crate) fn enqueue<T: Serialize>(
+    bg: &mut MySqlBackend,
+    recipients: Vec<String>,
+    reply_to: Option<&str>,
+    template: &str,
+    context: &T,
+) {
+    debug!(bg.log, "Queueing {} email to {:?}", template, recipients);
+    let context = serde_json::to_string(context).expect("failed to serialize email context");
+    let res = bg.transaction(|bg| {
+        bg.insert(
+            "outbox",
+            vec![
+                Value::NULL,
+                recipients.join("\n").into(),
+                reply_to.into(),
+                template.into(),
+                context.into(),
+                0.into(),
+                Local::now().naive_local().into(),
+                Value::NULL,
+                Value::NULL,
+            ],
+        )
+    });
+    if let Err(e) = res {
+        error!(
+            bg.log,
+            "failed to queue {} email to {:?}: {}", template, recipients, e
+        );
+    }
+}
+
+/// The delay before retrying an email that has failed `attempts` times.
+fn backoff(attempts: u64) -> ChronoDuration {
+    let secs = BASE_BACKOFF_SECS.saturating_mul(1 << attempts.saturating_sub(1).min(20));
+    ChronoDuration::seconds(secs.min(MAX_BACKOFF_SECS))
+}
+
+struct Queued {
+    id: u64,
+    recipients: Vec<String>,
+    reply_to: Option<String>,
+    template: String,
+    context: String,
+    attempts: u64,
+}
+
+/// Reads column `i` of an outbox row, naming the column if it holds an unexpected value.
+fn column<T: FromValue>(r: &[Value], i: usize, name: &str) -> Result<T, String> {
+    from_value_opt(r[i].clone()).map_err(|_| format!("invalid {} in the outbox", name))
+}
+
+fn queued_from_row(r: &[Value]) -> Result<Queued, String> {
+    Ok(Queued {
+        id: column(r, 0, "id")?,
+        recipients: column::<String>(r, 1, "recipients")?
+            .lines()
+            .map(String::from)
+            .collect(),
+        reply_to: column(r, 2, "reply_to")?,
+        template: column(r, 3, "template")?,
+        context: column(r, 4, "context")?,
+        attempts: column(r, 5, "attempts")?,
+    })
+}
+
+/// Emails that are due to be sent, oldest first. Rows that cannot be read are given up on, like
+/// emails that failed too often.
+fn due(bg: &mut MySqlBackend) -> Vec<Queued> {
+    let res = bg.prep_exec(
+        "SELECT id, recipients, reply_to, template, context, attempts FROM outbox \
+         WHERE sent_at IS NULL AND attempts < ? AND next_attempt <= ? ORDER BY id",
+        vec![MAX_ATTEMPTS.into(), Local::now().naive_local().into()],
+    );
+    let mut queued = vec![];
+    for r in res {
+        match queued_from_row(&r) {
+            Ok(q) => queued.push(q),
+            Err(e) => {
+                error!(bg.log, "giving up on outbox row {:?}: {}", r[0], e);
+                bg.prep_exec(
+                    "UPDATE outbox SET attempts = ?, last_error = ? WHERE id = ?",
+                    vec![MAX_ATTEMPTS.into(), e.into(), r[0].clone()],
+                );
+            }
+        }
+    }
+    queued
+}
+
+/// Renders and sends a queued email. Rendering happens here rather than when queueing, so
+/// emails that failed because of a broken template go out once the template is fixed.
+fn send(
+    log: slog::Logger,
+    config: &Config,
+    templates: &Templates,
+    q: &Queued,
+) -> Result<(), EmailError> {
+    let context: serde_json::Value = serde_json::from_str(&q.context)?;
+    let message = templates.render(&q.template, &context)?;
+    email::send(
+        log,
+        config,
+        q.recipients.clone(),
+        q.reply_to.clone(),
+        &message,
+    )
+}
+
+/// Sends all due emails. The backend lock is only held to read and update the outbox,
+/// never while talking to the mail server.
+fn send_due(backend: &Arc<Mutex<MySqlBackend>>, config: &Config, templates: &Templates) {
+    let mut bg = backend.lock().unwrap();
+    let queued = due(&mut bg);
+    let log = bg.log.clone();
+    drop(bg);
+
+    for q in queued {
+        let res = send(log.clone(), config, templates, &q);
+        let now = Local::now().naive_local();
+        let mut bg = backend.lock().unwrap();
+        match res {
+            Ok(()) => {
+                bg.prep_exec(
+                    "UPDATE outbox SET sent_at = ?, attempts = ? WHERE id = ?",
+                    vec![now.into(), (q.attempts + 1).into(), q.id.into()],
+                );
+            }
+            Err(e) => {
+                let attempts = q.attempts + 1;
+                if attempts >= MAX_ATTEMPTS {
+                    error!(
+                        log,
+                        "giving up on {} email {} after {} attempts: {}",
+                        q.template,
+                        q.id,
+                        attempts,
+                        e
+                    );
+                } else {
+                    warn!(
+                        log,
+                        "failed to send {} email {}, attempt {}: {}", q.template, q.id, attempts, e
+                    );
+                }
+                bg.prep_exec(
+                    "UPDATE outbox SET attempts = ?, next_attempt = ?, last_error = ? WHERE id = ?",
+                    vec![
+                        attempts.into(),
+                        (now + backoff(attempts)).into(),
+                        e.to_string().into(),
+                        q.id.into(),
+                    ],
+                );
+            }
+        }
+        drop(bg);
+    }
+}
+
+/// Starts a thread that sends queued emails, retrying failures with exponential backoff.
+pub(crate) fn start_worker(backend: Arc<Mutex<MySqlBackend>>, config: Config) {
+    let templates = Templates::load(&config.template_dir);
+    std::thread::spawn(move || loop {
+        send_due(&backend, &config, &templates);
+        std::thread::sleep(POLL_INTERVAL);
+    });
+}
@@ -0,0 +1,356 @@
+use crate::admin::Admin;
+use crate::apikey::ApiKey;
+use crate::backend::{MySqlBackend, Value};
+use crate::config::Config;
+use crate::grading;
+use crate::groups;
+use crate::questions::{self, LectureId, QuestionId};
+use crate::roster;
+use chrono::Local;
+use mysql::from_value;
+use rand::seq::SliceRandom;
+use rocket::form::Form;
+use rocket::response::status::BadRequest;
+use rocket::response::Redirect;
+use rocket::State;
+use rocket_dyn_templates::Template;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, FromForm)]
+pub(crate) struct AssignReviewsForm {
+    reviewers: usize,
+}
+
+#[derive(Debug, FromForm)]
+pub(crate) struct PeerReviewSubmission {
+    /// Reviews by submission number, then question
+    reviews: HashMap<usize, HashMap<u64, String>>,
+}
+
+#[derive(Serialize)]
+struct ReviewQuestion {
+    id: u64,
+    prompt: String,
+    answer: Option<String>,
+    review: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ReviewSubmission {
+    num: usize,
+    questions: Vec<ReviewQuestion>,
+}
+
+#[derive(Serialize)]
+struct PeerReviewContext {
+    lec_id: LectureId,
+    submissions: Vec<ReviewSubmission>,
+    parent: &'static str,
+}
+
+/// Assigns each of `authors` to `n` reviewers among the other authors. Authors are shuffled and
+/// then each reviews the next `n` authors in the shuffled order (wrapping around), so nobody
+/// reviews themselves and everyone gives and receives exactly `n` reviews.
+fn assign(authors: &[String], n: usize) -> Vec<(String, String)> {
+    let mut order = authors.to_vec();
+    order.shuffle(&mut rand::thread_rng());
+    let mut pairs = vec![];
+    for (i, reviewer) in order.iter().enumerate() {
+        for k in 1..=n {
+            pairs.push((reviewer.clone(), order[(i + k) % order.len()].clone()));
+        }
+    }
+    pairs
+}
+
+/// The authors a reviewer was assigned in a lecture, in the order the reviewer sees them. The
+/// order is by pseudonym, so it is stable but does not reveal anything about the authors. Group
+/// members share the assignments of whichever member was assigned.
+fn assigned_authors(
+    bg: &mut MySqlBackend,
+    config: &Config,
+    num: LectureId,
+    reviewer: &str,
+) -> Vec<String> {
+    let mut authors: Vec<String> = vec![];
+    for member in groups::members(bg, num, reviewer) {
+        authors.extend(
+            bg.prep_exec(
+                "SELECT author FROM peer_assignments WHERE lec = ? AND reviewer = ?",
+                vec![num.into(), member.into()],
+            )
+            .into_iter()
+            .map(|r| from_value::<String>(r[0].clone())),
+        );
+    }
+    authors.sort_by_key(|a| grading::pseudonym(config, num, a));
+    authors
+}
+
+/// Whether peer reviews have been assigned for a lecture, which freezes its answers.
+pub(crate) fn reviews_assigned(bg: &mut MySqlBackend, num: LectureId) -> bool {
+    !bg.prep_exec(
+        "SELECT lec FROM peer_assignments WHERE lec = ? LIMIT 1",
+        vec![num.into()],
+    )
+    .is_empty()
+}
+
+/// Number of submissions a student has been assigned to review in each lecture.
+pub(crate) fn assignment_counts(bg: &mut MySqlBackend, reviewer: &str) -> HashMap<LectureId, u64> {
+    bg.prep_exec(
+        "SELECT peer_assignments.lec, COUNT(peer_assignments.author) FROM peer_assignments \
+         WHERE peer_assignments.reviewer = ? OR peer_assignments.reviewer IN \
+         (SELECT other.email FROM group_members AS me \
+          JOIN group_members AS other ON (me.group_id = other.group_id) \
+          JOIN lec_groups ON (lec_groups.id = me.group_id) \
+          WHERE me.email = ? AND me.accepted = 1 AND other.accepted = 1 \
+          AND lec_groups.lec = peer_assignments.lec) \
+         GROUP BY peer_assignments.lec",
+        vec![reviewer.into(), reviewer.into()],
+    )
+    .into_iter()
+    .map(|r| (from_value(r[0].clone()), from_value(r[1].clone())))
+    .collect()
+}
+
+/// Reviews an author (or their group) received in a lecture, keyed by question.
+pub(crate) fn received_reviews(
+    bg: &mut MySqlBackend,
+    num: LectureId,
+    author: &str,
+) -> HashMap<QuestionId, Vec<String>> {
+    let mut rows = vec![];
+    for member in groups::members(bg, num, author) {
+        rows.extend(bg.prep_exec(
+            "SELECT q, review FROM peer_reviews WHERE lec = ? AND author = ? ORDER BY reviewer",
+            vec![num.into(), member.into()],
+        ));
+    }
+    let mut reviews = HashMap::new();
+    for r in rows {
+        let q: u64 = from_value(r[0].clone());
+        let review: String = from_value(r[1].clone());
+        if !review.trim().is_empty() {
+            reviews.entry(q).or_insert_with(Vec::new).push(review);
+        }
+    }
+    reviews
+}
+
+#[post("/peer/<num>", data = "<data>")]
+pub(crate) fn assign_reviews(
+    _adm: Admin,
+    num: LectureId,
+    data: Form<AssignReviewsForm>,
+    backend: &State<Arc<Mutex<MySqlBackend>>>,
+    config: &State<Config>,
+) -> Result<Redirect, BadRequest<String>> {
+    let mut bg = backend.lock().unwrap();
+    match questions::lecture_deadline(&mut bg, num) {
+        Some(deadline) if deadline <= Local::now().naive_local() => (),
+        Some(_) => {
+            return Err(BadRequest(Some(
+                "Peer reviews can only be assigned after the deadline.".into(),
+            )))
+        }
+        None => {
+            return Err(BadRequest(Some(
+                "Set a deadline before assigning peer reviews.".into(),
+            )))
+        }
+    }
+    let reviewed = bg.prep_exec(
+        "SELECT COUNT(*) FROM peer_reviews WHERE lec = ?",
+        vec![num.into()],
+    );
+    if from_value::<u64>(reviewed[0][0].clone()) > 0 {
+        return Err(BadRequest(Some(
+            "Reviews have already been submitted for this lecture.".into(),
+        )));
+    }
+
+    // staff test submissions are not reviewed, and group members share one submission, so each
+    // group is reviewed once under one of its members
+    let students: HashSet<String> = roster::students(&mut bg, config)
+        .into_iter()
+        .map(|s| s.email)
+        .collect();
+    let answered: Vec<String> = bg
+        .prep_exec(
+            "SELECT DISTINCT email FROM answers WHERE lec = ? ORDER BY email",
+            vec![num.into()],
+        )
+        .into_iter()
+        .map(|r| from_value(r[0].clone()))
+        .filter(|e| students.contains(e))
+        .collect();
+    let mut authors: Vec<String> = vec![];
+    for email in answered {
+        let members = groups::members(&mut bg, num, &email);
+        if !authors.iter().any(|a| members.contains(a)) {
+            authors.push(email);
+        }
+    }
+    if data.reviewers == 0 || data.reviewers >= authors.len() {
+        return Err(BadRequest(Some(format!(
+            "Each student can review between 1 and {} others.",
+            authors.len().saturating_sub(1)
+        ))));
+    }
+
+    bg.prep_exec(
+        "DELETE FROM peer_assignments WHERE lec = ?",
+        vec![num.into()],
+    );
+    for (reviewer, author) in assign(&authors, data.reviewers) {
+        bg.insert(
+            "peer_assignments",
+            vec![num.into(), reviewer.into(), author.into()],
+        );
+    }
+    drop(bg);
+
+    Ok(Redirect::to(format!("/admin/lec/{}", num)))
+}
+
+#[get("/<num>")]
+pub(crate) fn reviews(
+    apikey: ApiKey,
+    num: LectureId,
+    backend: &State<Arc<Mutex<MySqlBackend>>>,
+    config: &State<Config>,
+) -> Template {
+    let mut bg = backend.lock().unwrap();
+    let authors = assigned_authors(&mut bg, config, num, &apikey.user);
+    let mut qres = bg.prep_exec("SELECT * FROM questions WHERE lec = ?", vec![num.into()]);
+    qres.sort_by_key(|r| questions::question_order(r));
+    let qs: Vec<(u64, String)> = qres
+        .into_iter()
+        .map(|r| (from_value(r[1].clone()), from_value(r[2].clone())))
+        .collect();
+
+    let mut submissions = vec![];
+    for (i, author) in authors.iter().enumerate() {
+        let answers: HashMap<u64, String> = bg
+            .prep_exec(
+                "SELECT q, answer FROM answers WHERE lec = ? AND email = ?",
+                vec![num.into(), author.clone().into()],
+            )
+            .into_iter()
+            .map(|r| (from_value(r[0].clone()), from_value(r[1].clone())))
+            .collect();
+        let reviews: HashMap<u64, String> = bg
+            .prep_exec(
+                "SELECT q, review FROM peer_reviews WHERE lec = ? AND reviewer = ? AND author = ?",
+                vec![
+                    num.into(),
+                    apikey.user.clone().into(),
+                    author.clone().into(),
+                ],
+            )
+            .into_iter()
+            .map(|r| (from_value(r[0].clone()), from_value(r[1].clone())))
+            .collect();
+        submissions.push(ReviewSubmission {
+            num: i + 1,
+            questions: qs
+                .iter()
+                .map(|(id, prompt)| ReviewQuestion {
+                    id: *id,
+                    prompt: prompt.clone(),
+                    answer: answers.get(id).cloned(),
+                    review: reviews.get(id).cloned(),
+                })
+                .collect(),
+        });
+    }
+    drop(bg);
+
+    let ctx = PeerReviewContext {
+        lec_id: num,
+        submissions: submissions,
+        parent: "layout",
+    };
+    Template::render("reviews", &ctx)
+}
+
+#[post("/<num>", data = "<data>")]
+pub(crate) fn reviews_submit(
+    apikey: ApiKey,
+    num: LectureId,
+    data: Form<PeerReviewSubmission>,
+    backend: &State<Arc<Mutex<MySqlBackend>>>,
+    config: &State<Config>,
+) -> Result<Redirect, BadRequest<String>> {
+    let mut bg = backend.lock().unwrap();
+    let authors = assigned_authors(&mut bg, config, num, &apikey.user);
+    let qids: Vec<QuestionId> = bg
+        .prep_exec("SELECT q FROM questions WHERE lec = ?", vec![num.into()])
+        .into_iter()
+        .map(|r| from_value(r[0].clone()))
+        .collect();
+    let ts: Value = Local::now().naive_local().into();
+
+    // check the whole form before storing any review
+    let mut checked = vec![];
+    for (sub, reviews) in &data.reviews {
+        // submissions are numbered from one on the review page
+        let author = match sub.checked_sub(1).and_then(|i| authors.get(i)) {
+            Some(author) => author,
+            None => {
+                return Err(BadRequest(Some(format!(
+                    "You were not assigned submission {}.",
+                    sub
+                ))))
+            }
+        };
+        if let Some(q) = reviews.keys().find(|q| !qids.contains(q)) {
+            return Err(BadRequest(Some(format!(
+                "Lecture {} has no question {}.",
+                num, q
+            ))));
+        }
+        checked.push((author, reviews));
+    }
+    for (author, reviews) in checked {
+        for (q, review) in reviews {
+            bg.replace(
+                "peer_reviews",
+                vec![
+                    num.into(),
+                    apikey.user.clone().into(),
+                    author.clone().into(),
+                    (*q).into(),
+                    review.clone().into(),
+                    ts.clone(),
+                ],
+            );
+        }
+    }
+    drop(bg);
+
+    Ok(Redirect::to("/leclist"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assign_gives_and_receives_n_reviews() {
+        let authors: Vec<String> = (0..7).map(|i| format!("s{}@example.com", i)).collect();
+        for n in 1..authors.len() {
+            let pairs = assign(&authors, n);
+            assert_eq!(pairs.len(), authors.len() * n);
+            assert!(pairs.iter().all(|(r, a)| r != a));
+            let unique: HashSet<&(String, String)> = pairs.iter().collect();
+            assert_eq!(unique.len(), pairs.len());
+            for s in &authors {
+                assert_eq!(pairs.iter().filter(|(r, _)| r == s).count(), n);
+                assert_eq!(pairs.iter().filter(|(_, a)| a == s).count(), n);
+            }
+        }
+    }
+}
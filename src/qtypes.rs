@@ -0,0 +1,145 @@
+/// The kinds of questions a lecture can ask.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum QuestionType {
+    /// Free-form answer (multi-line textarea)
+    Text,
+    /// Short answer (single-line input)
+    ShortText,
+    /// Multiple choice, exactly one option may be selected
+    SingleChoice,
+    /// Multiple choice, any number of options may be selected
+    MultiChoice,
+    /// Numeric answer
+    Numeric,
+    /// File upload; the options list the accepted file extensions
+    File,
+}
+
+/// Maximum length of a short-answer response.
+const SHORT_ANSWER_MAX_LEN: usize = 255;
+
+impl QuestionType {
+    pub(crate) fn from_name(s: &str) -> Option<QuestionType> {
+        match s {
+            "text" => Some(QuestionType::Text),
+            "short" => Some(QuestionType::ShortText),
+            "single" => Some(QuestionType::SingleChoice),
+            "multi" => Some(QuestionType::MultiChoice),
+            "numeric" => Some(QuestionType::Numeric),
+            "file" => Some(QuestionType::File),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            QuestionType::Text => "text",
+            QuestionType::ShortText => "short",
+            QuestionType::SingleChoice => "single",
+            QuestionType::MultiChoice => "multi",
+            QuestionType::Numeric => "numeric",
+            QuestionType::File => "file",
+        }
+    }
+
+    pub(crate) fn is_choice(&self) -> bool {
+        *self == QuestionType::SingleChoice || *self == QuestionType::MultiChoice
+    }
+
+    /// Whether questions of this type have a list of options.
+    pub(crate) fn has_options(&self) -> bool {
+        self.is_choice() || *self == QuestionType::File
+    }
+
+    /// Checks that the submitted values form a valid answer for a question of this type, and
+    /// returns the answer text to store. Multiple-choice selections are stored one per line.
+    pub(crate) fn validate(&self, options: &[String], values: &[String]) -> Result<String, String> {
+        match self {
+            QuestionType::Text => Ok(values.join("\n")),
+            QuestionType::ShortText => {
+                let answer = values.join(" ");
+                if answer.contains('\n') {
+                    Err("short answers must fit on a single line".into())
+                } else if answer.chars().count() > SHORT_ANSWER_MAX_LEN {
+                    Err(format!(
+                        "short answers are limited to {} characters",
+                        SHORT_ANSWER_MAX_LEN
+                    ))
+                } else {
+                    Ok(answer.trim().to_string())
+                }
+            }
+            QuestionType::SingleChoice | QuestionType::MultiChoice => {
+                // multiple choice forms send an empty value even when no box is ticked
+                let values: Vec<&String> = values.iter().filter(|v| !v.is_empty()).collect();
+                if *self == QuestionType::SingleChoice && values.len() > 1 {
+                    return Err("only one option may be selected".into());
+                }
+                for v in &values {
+                    if !options.contains(*v) {
+                        return Err(format!("\"{}\" is not one of the options", v));
+                    }
+                }
+                // keep the selections in the order the options are listed
+                Ok(options
+                    .iter()
+                    .filter(|o| values.contains(o))
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join("\n"))
+            }
+            QuestionType::Numeric => {
+                let answer = values.join("").trim().to_string();
+                if answer.is_empty() {
+                    Ok(answer)
+                } else {
+                    match answer.parse::<f64>() {
+                        Ok(v) if v.is_finite() => Ok(answer),
+                        _ => Err(format!("\"{}\" is not a number", answer)),
+                    }
+                }
+            }
+            QuestionType::File => Err("expects a file upload".into()),
+        }
+    }
+
+    /// Checks an answer against the answer key. Returns `None` if questions of this type cannot
+    /// be graded automatically, or if the answer key is unusable.
+    pub(crate) fn is_correct(
+        &self,
+        key: &str,
+        tolerance: Option<f64>,
+        answer: &str,
+    ) -> Option<bool> {
+        match self {
+            QuestionType::Text | QuestionType::ShortText | QuestionType::File => None,
+            QuestionType::SingleChoice | QuestionType::MultiChoice => {
+                let mut expected = parse_options(key);
+                let mut given = parse_options(answer);
+                if expected.is_empty() {
+                    return None;
+                }
+                expected.sort();
+                given.sort();
+                Some(expected == given)
+            }
+            QuestionType::Numeric => {
+                let expected = key.trim().parse::<f64>().ok()?;
+                match answer.trim().parse::<f64>() {
+                    Ok(given) => Some((expected - given).abs() <= tolerance.unwrap_or(0.0)),
+                    Err(_) => Some(false),
+                }
+            }
+        }
+    }
+}
+
+/// Splits the stored option list of a multiple-choice question (one option per line).
+pub(crate) fn parse_options(options: &str) -> Vec<String> {
+    options
+        .lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty())
+        .map(String::from)
+        .collect()
+}
@@ -2,61 +2,250 @@ use crate::admin::Admin;
 use crate::apikey::ApiKey;
 use crate::backend::{MySqlBackend, Value};
 use crate::config::Config;
-use crate::email;
+use crate::grading;
+use crate::groups::{self, Group};
+use crate::notifications;
+use crate::peer;
+use crate::qtypes::{self, QuestionType};
+use crate::receipts::{self, Receipt};
+use crate::regrades::{self, Regrade};
+use crate::rubric::{self, RubricItem};
+use crate::uploads;
 use chrono::naive::NaiveDateTime;
 use chrono::Local;
 use mysql::from_value;
 use rocket::form::{Form, FromForm};
-use rocket::response::Redirect;
+use rocket::fs::TempFile;
+use rocket::response::status::BadRequest;
 use rocket::State;
 use rocket_dyn_templates::Template;
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 
+/// Lecture numbers, as used in URLs and the `bigint unsigned` `lec` columns. Like `QuestionId`,
+/// this is a plain alias for readability, so the compiler does not tell the two apart.
+pub(crate) type LectureId = u64;
+/// Question numbers within a lecture, as used in URLs and the `bigint unsigned` `q` columns.
+pub(crate) type QuestionId = u64;
+
 //pub(crate) enum LectureQuestionFormError {
 //   Invalid,
 //}
 
 #[derive(Debug, FromForm)]
-pub(crate) struct LectureQuestionSubmission {
-    answers: HashMap<u64, String>,
+pub(crate) struct LectureQuestionSubmission<'r> {
+    answers: HashMap<QuestionId, Vec<String>>,
+    files: HashMap<QuestionId, TempFile<'r>>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct QuestionOption {
+    pub value: String,
+    pub selected: bool,
 }
 
 #[derive(Serialize)]
 pub(crate) struct LectureQuestion {
-    pub id: u64,
+    pub id: QuestionId,
     pub prompt: String,
+    pub qtype: &'static str,
+    pub options: Vec<QuestionOption>,
+    pub tolerance: Option<f64>,
+    pub answer_key: Option<String>,
+    pub points: f64,
     pub answer: Option<String>,
+    pub grade: Option<f64>,
+    pub feedback: Option<String>,
+    pub rubric: Vec<RubricItem>,
+    pub reviews: Vec<String>,
+    pub regrade: Option<Regrade>,
+}
+
+/// Extracts the question type and multiple-choice options from a row of the `questions` table.
+fn question_type_from_row(r: &[Value]) -> (QuestionType, Vec<String>) {
+    let qtype =
+        QuestionType::from_name(&from_value::<String>(r[3].clone())).unwrap_or(QuestionType::Text);
+    let options = if r[4] == Value::NULL {
+        vec![]
+    } else {
+        qtypes::parse_options(&from_value::<String>(r[4].clone()))
+    };
+    (qtype, options)
+}
+
+/// Builds a `LectureQuestion` from a row of the `questions` table.
+pub(crate) fn question_from_row(r: &[Value], answer: Option<String>) -> LectureQuestion {
+    let (qtype, options) = question_type_from_row(r);
+    let selected: Vec<&str> = match answer {
+        Some(ref a) if qtype.is_choice() => a.lines().collect(),
+        _ => vec![],
+    };
+    LectureQuestion {
+        id: from_value(r[1].clone()),
+        prompt: from_value(r[2].clone()),
+        qtype: qtype.as_str(),
+        options: options
+            .into_iter()
+            .map(|o| QuestionOption {
+                selected: selected.contains(&o.as_str()),
+                value: o,
+            })
+            .collect(),
+        tolerance: if r[5] == Value::NULL {
+            None
+        } else {
+            Some(from_value(r[5].clone()))
+        },
+        answer_key: if r[6] == Value::NULL {
+            None
+        } else {
+            Some(from_value(r[6].clone()))
+        },
+        points: question_points(r),
+        answer: answer,
+        grade: None,
+        feedback: None,
+        rubric: vec![],
+        reviews: vec![],
+        regrade: None,
+    }
+}
+
+/// Points a question is worth (one unless configured otherwise).
+pub(crate) fn question_points(r: &[Value]) -> f64 {
+    if r[7] == Value::NULL {
+        1.0
+    } else {
+        from_value(r[7].clone())
+    }
+}
+
+/// Sort key for the question in row `r`: its position in the lecture, then its ID.
+pub(crate) fn question_order(r: &[Value]) -> (u64, u64) {
+    let id: u64 = from_value(r[1].clone());
+    let position = if r[8] == Value::NULL {
+        id
+    } else {
+        from_value(r[8].clone())
+    };
+    (position, id)
+}
+
+/// Grades an answer to the question in row `r` against its answer key. Returns `None` if the
+/// question has no answer key or is not of an automatically gradable type.
+fn autograde(r: &[Value], answer: &str) -> Option<f64> {
+    if r[6] == Value::NULL {
+        return None;
+    }
+    let (qtype, _) = question_type_from_row(r);
+    let tolerance = if r[5] == Value::NULL {
+        None
+    } else {
+        Some(from_value(r[5].clone()))
+    };
+    qtype
+        .is_correct(&from_value::<String>(r[6].clone()), tolerance, answer)
+        .map(|correct| if correct { question_points(r) } else { 0.0 })
+}
+
+/// The label of a lecture, or an empty string if there is no such lecture.
+pub(crate) fn lecture_label(bg: &mut MySqlBackend, num: LectureId) -> String {
+    let res = bg.prep_exec("SELECT label FROM lectures WHERE id = ?", vec![num.into()]);
+    match res.get(0) {
+        Some(r) => from_value(r[0].clone()),
+        None => String::new(),
+    }
+}
+
+/// The submission deadline of a lecture, if one is set.
+pub(crate) fn lecture_deadline(bg: &mut MySqlBackend, num: LectureId) -> Option<NaiveDateTime> {
+    let res = bg.prep_exec(
+        "SELECT deadline FROM lectures WHERE id = ?",
+        vec![num.into()],
+    );
+    match res.get(0) {
+        Some(r) if r[0] != Value::NULL => Some(from_value(r[0].clone())),
+        _ => None,
+    }
+}
+
+/// Reads a nullable `datetime` column.
+pub(crate) fn opt_datetime(v: &Value) -> Option<NaiveDateTime> {
+    if *v == Value::NULL {
+        None
+    } else {
+        Some(from_value(v.clone()))
+    }
+}
+
+/// A student's progress on a lecture: "late" if they submitted anything after the deadline,
+/// otherwise "not started", "partial" or "complete" depending on how many questions they
+/// answered.
+pub(crate) fn lecture_status(
+    num_answered: u64,
+    num_qs: u64,
+    last_submitted: Option<NaiveDateTime>,
+    deadline: Option<NaiveDateTime>,
+) -> &'static str {
+    match (last_submitted, deadline) {
+        _ if num_answered == 0 => "not started",
+        (Some(submitted), Some(deadline)) if submitted > deadline => "late",
+        _ if num_answered >= num_qs => "complete",
+        _ => "partial",
+    }
+}
+
+#[derive(Serialize)]
+struct ReceiptContext {
+    receipt: Receipt,
+    /// Whether a copy of the receipt is emailed to the student
+    emailed: bool,
+    parent: &'static str,
 }
 
 #[derive(Serialize)]
 pub(crate) struct LectureQuestionsContext {
-    pub lec_id: u8,
+    pub lec_id: LectureId,
+    pub label: String,
     pub questions: Vec<LectureQuestion>,
+    pub grades_released: bool,
+    pub blind_grading: bool,
+    pub deadline: Option<NaiveDateTime>,
+    pub notifications: Option<notifications::Settings>,
+    pub group: Option<Group>,
+    pub groups: Vec<Group>,
     pub parent: &'static str,
 }
 
 #[derive(Serialize)]
 struct LectureAnswer {
-    id: u64,
+    id: QuestionId,
     user: String,
     answer: String,
     time: Option<NaiveDateTime>,
+    score: Option<f64>,
+    sha256: Option<String>,
+    group: Option<String>,
 }
 
 #[derive(Serialize)]
 struct LectureAnswersContext {
-    lec_id: u8,
+    lec_id: LectureId,
+    blind: bool,
     answers: Vec<LectureAnswer>,
     parent: &'static str,
 }
 
 #[derive(Serialize)]
 struct LectureListEntry {
-    id: u64,
+    id: LectureId,
     label: String,
     num_qs: u64,
     num_answered: u64,
+    status: &'static str,
+    score: Option<f64>,
+    max_score: Option<f64>,
+    num_reviews: u64,
 }
 
 #[derive(Serialize)]
@@ -74,27 +263,66 @@ pub(crate) fn leclist(
 ) -> Template {
     let mut bg = backend.lock().unwrap();
     let res = bg.prep_exec(
-        "SELECT lectures.id, lectures.label, lec_qcount.qcount \
+        "SELECT lectures.id, lectures.label, lec_qcount.qcount, lec_maxscore.maxscore, \
+         lectures.deadline, lec_answered.answered, lec_answered.last_submitted \
          FROM lectures \
-         LEFT JOIN lec_qcount ON (lectures.id = lec_qcount.lec)",
-        vec![],
+         LEFT JOIN lec_qcount ON (lectures.id = lec_qcount.lec) \
+         LEFT JOIN lec_maxscore ON (lectures.id = lec_maxscore.lec) \
+         LEFT JOIN lec_answered ON (lectures.id = lec_answered.lec AND lec_answered.email = ?)",
+        vec![apikey.user.clone().into()],
+    );
+    let scores_res = bg.prep_exec(
+        "SELECT lec, SUM(score) FROM answers WHERE email = ? AND score IS NOT NULL GROUP BY lec",
+        vec![apikey.user.clone().into()],
     );
+    let reviews = peer::assignment_counts(&mut bg, &apikey.user);
     drop(bg);
 
+    let mut scores = HashMap::new();
+    for r in scores_res {
+        let lec: u64 = from_value(r[0].clone());
+        let score: f64 = from_value(r[1].clone());
+        scores.insert(lec, score);
+    }
+
     let user = apikey.user.clone();
     let admin = config.admins.contains(&user);
 
     let lecs: Vec<_> = res
         .into_iter()
-        .map(|r| LectureListEntry {
-            id: from_value(r[0].clone()),
-            label: from_value(r[1].clone()),
-            num_qs: if r[2] == Value::NULL {
+        .map(|r| {
+            let id: u64 = from_value(r[0].clone());
+            let max_score: Option<f64> = if r[3] == Value::NULL {
+                None
+            } else {
+                Some(from_value(r[3].clone()))
+            };
+            let num_qs = if r[2] == Value::NULL {
                 0u64
             } else {
                 from_value(r[2].clone())
-            },
-            num_answered: 0u64,
+            };
+            let num_answered = if r[5] == Value::NULL {
+                0u64
+            } else {
+                from_value(r[5].clone())
+            };
+            LectureListEntry {
+                id: id,
+                label: from_value(r[1].clone()),
+                num_qs: num_qs,
+                num_answered: num_answered,
+                status: lecture_status(
+                    num_answered,
+                    num_qs,
+                    opt_datetime(&r[6]),
+                    opt_datetime(&r[4]),
+                ),
+                // only show a score for lectures that have auto-graded questions
+                score: max_score.map(|_| scores.get(&id).cloned().unwrap_or(0.0)),
+                max_score: max_score,
+                num_reviews: reviews.get(&id).cloned().unwrap_or(0),
+            }
         })
         .collect();
 
@@ -110,29 +338,90 @@ pub(crate) fn leclist(
 #[get("/<num>")]
 pub(crate) fn answers(
     _admin: Admin,
-    num: u8,
+    num: LectureId,
     backend: &State<Arc<Mutex<MySqlBackend>>>,
+    config: &State<Config>,
 ) -> Template {
     let mut bg = backend.lock().unwrap();
-    let key: Value = (num as u64).into();
-    let res = bg.prep_exec("SELECT * FROM answers WHERE lec = ?", vec![key]);
+    let blind = grading::is_blind(&mut bg, num);
+    let key: Value = num.into();
+    let res = bg.prep_exec(
+        "SELECT answers.*, uploads.sha256 FROM answers \
+         LEFT JOIN uploads ON (answers.email = uploads.email \
+         AND answers.lec = uploads.lec AND answers.q = uploads.q) \
+         WHERE answers.lec = ?",
+        vec![key],
+    );
+    let lec_groups = groups::lecture_groups(&mut bg, num);
     drop(bg);
+
+    let display = |email: &str| {
+        if blind {
+            grading::pseudonym(config, num, email)
+        } else {
+            email.to_string()
+        }
+    };
+    // group members all hold a copy of the group's answers; list each answer once, under the
+    // group
+    let mut group_of = HashMap::new();
+    for g in &lec_groups {
+        let members: Vec<String> = g
+            .members
+            .iter()
+            .filter(|m| m.accepted)
+            .map(|m| display(&m.email))
+            .collect();
+        // students choose group names, so they are hidden while grading is blind
+        let label = if blind {
+            format!("group {} ({})", g.id, members.join(", "))
+        } else {
+            format!("{} ({})", g.name, members.join(", "))
+        };
+        for m in g.members.iter().filter(|m| m.accepted) {
+            group_of.insert(m.email.clone(), (g.id, label.clone()));
+        }
+    }
+    let mut seen = std::collections::HashSet::new();
+
     let answers: Vec<_> = res
         .into_iter()
-        .map(|r| LectureAnswer {
-            id: from_value(r[2].clone()),
-            user: from_value(r[0].clone()),
-            answer: from_value(r[3].clone()),
-            time: if let Value::Time(..) = r[4] {
-                Some(from_value::<NaiveDateTime>(r[4].clone()))
-            } else {
-                None
-            },
+        .filter_map(|r| {
+            let email: String = from_value(r[0].clone());
+            let id: u64 = from_value(r[2].clone());
+            let group = group_of.get(&email).cloned();
+            if let Some((gid, _)) = group {
+                if !seen.insert((gid, id)) {
+                    return None;
+                }
+            }
+            Some(LectureAnswer {
+                id: id,
+                user: display(&email),
+                answer: from_value(r[3].clone()),
+                time: if let Value::Time(..) = r[4] {
+                    Some(from_value::<NaiveDateTime>(r[4].clone()))
+                } else {
+                    None
+                },
+                score: if r[5] == Value::NULL {
+                    None
+                } else {
+                    Some(from_value(r[5].clone()))
+                },
+                sha256: if r[6] == Value::NULL {
+                    None
+                } else {
+                    Some(from_value(r[6].clone()))
+                },
+                group: group.map(|(_, label)| label),
+            })
         })
         .collect();
 
     let ctx = LectureAnswersContext {
         lec_id: num,
+        blind: blind,
         answers: answers,
         parent: "layout",
     };
@@ -142,97 +431,278 @@ pub(crate) fn answers(
 #[get("/<num>")]
 pub(crate) fn questions(
     apikey: ApiKey,
-    num: u8,
+    num: LectureId,
     backend: &State<Arc<Mutex<MySqlBackend>>>,
 ) -> Template {
     use std::collections::HashMap;
 
     let mut bg = backend.lock().unwrap();
-    let key: Value = (num as u64).into();
+    let key: Value = num.into();
 
     let answers_res = bg.prep_exec(
         "SELECT answers.* FROM answers WHERE answers.lec = ? AND answers.email = ?",
-        vec![(num as u64).into(), apikey.user.clone().into()],
+        vec![num.into(), apikey.user.clone().into()],
     );
     let mut answers = HashMap::new();
+    let mut autoscores = HashMap::new();
 
     for r in answers_res {
         let id: u64 = from_value(r[2].clone());
         let atext: String = from_value(r[3].clone());
         answers.insert(id, atext);
+        if r[5] != Value::NULL {
+            autoscores.insert(id, from_value::<f64>(r[5].clone()));
+        }
     }
-    let res = bg.prep_exec("SELECT * FROM questions WHERE lec = ?", vec![key]);
+
+    // grades and feedback only become visible once staff release them
+    let released = grading::grades_released(&mut bg, num);
+    let mut grades = HashMap::new();
+    let mut rubrics = HashMap::new();
+    let mut applied = HashMap::new();
+    let mut regrades = HashMap::new();
+    if released {
+        // later requests replace earlier ones, leaving the latest per question
+        for r in regrades::user_requests(&mut bg, num, &apikey.user) {
+            regrades.insert(r.q, r);
+        }
+        rubrics = rubric::lecture_items(&mut bg, num);
+        applied = rubric::applied(&mut bg, num, &apikey.user);
+        let grades_res = bg.prep_exec(
+            "SELECT q, score, comment FROM grades WHERE lec = ? AND email = ?",
+            vec![num.into(), apikey.user.clone().into()],
+        );
+        for r in grades_res {
+            let id: u64 = from_value(r[0].clone());
+            let score: Option<f64> = if r[1] == Value::NULL {
+                None
+            } else {
+                Some(from_value(r[1].clone()))
+            };
+            let comment: Option<String> = if r[2] == Value::NULL {
+                None
+            } else {
+                Some(from_value(r[2].clone()))
+            };
+            grades.insert(id, (score, comment));
+        }
+    }
+
+    let mut reviews = peer::received_reviews(&mut bg, num, &apikey.user);
+    let label = lecture_label(&mut bg, num);
+    let deadline = lecture_deadline(&mut bg, num);
+    let group = groups::user_group(&mut bg, num, &apikey.user);
+
+    let mut res = bg.prep_exec("SELECT * FROM questions WHERE lec = ?", vec![key]);
     drop(bg);
-    let mut qs: Vec<_> = res
+    res.sort_by_key(|r| question_order(r));
+    let qs: Vec<_> = res
         .into_iter()
         .map(|r| {
             let id: u64 = from_value(r[1].clone());
             let answer = answers.get(&id).map(|s| s.to_owned());
-            LectureQuestion {
-                id: id,
-                prompt: from_value(r[2].clone()),
-                answer: answer,
+            let mut q = question_from_row(&r, answer);
+            // never reveal the answer key to students
+            q.answer_key = None;
+            q.reviews = reviews.remove(&id).unwrap_or_default();
+            if released {
+                let (score, comment) = grades.remove(&id).unwrap_or((None, None));
+                // a manual grade overrides the automatic score
+                q.grade = score.or_else(|| autoscores.get(&id).cloned());
+                q.feedback = comment.filter(|c| !c.is_empty());
+                q.regrade = regrades.remove(&id);
+                // show the student which rubric items were applied to their answer
+                let applied = applied.get(&id).cloned().unwrap_or_default();
+                q.rubric = rubrics
+                    .remove(&id)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter(|item| applied.contains(&item.id))
+                    .map(|item| RubricItem {
+                        applied: true,
+                        ..item
+                    })
+                    .collect();
             }
+            q
         })
         .collect();
-    qs.sort_by(|a, b| a.id.cmp(&b.id));
 
     let ctx = LectureQuestionsContext {
         lec_id: num,
+        label: label,
         questions: qs,
+        grades_released: released,
+        blind_grading: false,
+        deadline: deadline,
+        notifications: None,
+        group: group,
+        groups: vec![],
         parent: "layout",
     };
     Template::render("questions", &ctx)
 }
 
 #[post("/<num>", data = "<data>")]
-pub(crate) fn questions_submit(
+pub(crate) async fn questions_submit(
     apikey: ApiKey,
-    num: u8,
-    data: Form<LectureQuestionSubmission>,
+    num: LectureId,
+    mut data: Form<LectureQuestionSubmission<'_>>,
     backend: &State<Arc<Mutex<MySqlBackend>>>,
     config: &State<Config>,
-) -> Redirect {
-    let mut bg = backend.lock().unwrap();
-    let vnum: Value = (num as u64).into();
-    let ts: Value = Local::now().naive_local().into();
-
-    for (id, answer) in &data.answers {
-        let rec: Vec<Value> = vec![
-            apikey.user.clone().into(),
-            vnum.clone(),
-            (*id).into(),
-            answer.clone().into(),
-            ts.clone(),
-        ];
-        bg.replace("answers", rec);
-    }
-
-    let answer_log = format!(
-        "{}",
-        data.answers
-            .iter()
-            .map(|(i, t)| format!("Question {}:\n{}", i, t))
-            .collect::<Vec<_>>()
-            .join("\n-----\n")
-    );
-    if config.send_emails {
-        let recipients = if num < 90 {
-            config.staff.clone()
-        } else {
-            config.admins.clone()
-        };
+) -> Result<Template, BadRequest<String>> {
+    let vnum: Value = num.into();
 
-        email::send(
-            bg.log.clone(),
-            apikey.user.clone(),
-            recipients,
-            format!("{} meeting {} questions", config.class, num),
-            answer_log,
+    // check every answer against its question's type before storing anything
+    let (qres, frozen) = {
+        let mut bg = backend.lock().unwrap();
+        (
+            bg.prep_exec("SELECT * FROM questions WHERE lec = ?", vec![vnum.clone()]),
+            peer::reviews_assigned(&mut bg, num),
         )
-        .expect("failed to send email");
+    };
+    // late submissions are accepted (and shown as late), unless peer reviewers were already
+    // assigned the answers
+    if frozen {
+        return Err(BadRequest(Some(format!(
+            "Peer reviews for lecture {} have been assigned, so answers can no longer be changed.",
+            num
+        ))));
+    }
+    let mut qrows = HashMap::new();
+    for r in qres {
+        let id: u64 = from_value(r[1].clone());
+        qrows.insert(id, r);
+    }
+
+    let mut answers = HashMap::new();
+    let mut errors = vec![];
+    for (id, values) in &data.answers {
+        match qrows.get(id) {
+            None => errors.push(format!("Question {}: no such question", id)),
+            Some(r) => {
+                let (qtype, options) = question_type_from_row(r);
+                match qtype.validate(&options, values) {
+                    Ok(answer) => {
+                        answers.insert(*id, answer);
+                    }
+                    Err(e) => errors.push(format!("Question {}: {}", id, e)),
+                }
+            }
+        }
+    }
+    for (id, file) in &data.files {
+        // an empty file input means the student kept their previous upload
+        if file.len() == 0 {
+            continue;
+        }
+        match qrows.get(id).map(|r| question_type_from_row(r)) {
+            Some((QuestionType::File, extensions)) => {
+                if let Err(e) = uploads::check(config, &extensions, file) {
+                    errors.push(format!("Question {}: {}", id, e));
+                }
+            }
+            Some(_) => errors.push(format!("Question {}: does not accept files", id)),
+            None => errors.push(format!("Question {}: no such question", id)),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(BadRequest(Some(errors.join("\n"))));
+    }
+
+    // store uploaded files before taking the backend lock again
+    let mut files = HashMap::new();
+    for (id, file) in data.files.iter_mut() {
+        if file.len() == 0 {
+            continue;
+        }
+        match uploads::store(config, file).await {
+            Ok(stored) => {
+                files.insert(*id, stored);
+            }
+            Err(e) => {
+                return Err(BadRequest(Some(format!(
+                    "Question {}: failed to store upload ({})",
+                    id, e
+                ))))
+            }
+        }
     }
+
+    let mut bg = backend.lock().unwrap();
+    let now = Local::now().naive_local();
+    let ts: Value = now.into();
+    // group members share one submission, so everyone in the group gets a copy
+    let members = groups::members(&mut bg, num, &apikey.user);
+
+    for member in &members {
+        for (id, answer) in &answers {
+            let score = autograde(&qrows[id], answer);
+            let rec: Vec<Value> = vec![
+                member.clone().into(),
+                vnum.clone(),
+                (*id).into(),
+                answer.clone().into(),
+                ts.clone(),
+                score.into(),
+            ];
+            bg.replace("answers", rec);
+        }
+        for (id, file) in &files {
+            bg.replace(
+                "answers",
+                vec![
+                    member.clone().into(),
+                    vnum.clone(),
+                    (*id).into(),
+                    file.filename.clone().into(),
+                    ts.clone(),
+                    Value::NULL,
+                ],
+            );
+            bg.replace(
+                "uploads",
+                vec![
+                    member.clone().into(),
+                    vnum.clone(),
+                    (*id).into(),
+                    file.filename.clone().into(),
+                    file.content_type.clone().into(),
+                    file.size.into(),
+                    file.sha256.clone().into(),
+                ],
+            );
+        }
+    }
+    for (id, file) in &files {
+        answers.insert(
+            *id,
+            format!(
+                "[uploaded {}, {} bytes, sha256 {}]",
+                file.filename, file.size, file.sha256
+            ),
+        );
+    }
+
+    let mut submitted: Vec<notifications::SubmittedAnswer> = answers
+        .into_iter()
+        .map(|(q, answer)| notifications::SubmittedAnswer {
+            q: q,
+            answer: answer,
+        })
+        .collect();
+    submitted.sort_by_key(|a| a.q);
+    if config.send_emails {
+        notifications::submitted(&mut bg, config, num, &apikey.user, &submitted);
+    }
+    let receipt = Receipt::new(config, num, &apikey.user, now, submitted);
+    receipts::send(&mut bg, config, &receipt);
     drop(bg);
 
-    Redirect::to("/leclist")
+    let ctx = ReceiptContext {
+        receipt: receipt,
+        emailed: config.send_emails && config.send_receipts,
+        parent: "layout",
+    };
+    Ok(Template::render("receipt", &ctx))
 }
@@ -0,0 +1,117 @@
+use crate::backend::MySqlBackend;
+use crate::config::Config;
+use crate::notifications::SubmittedAnswer;
+use crate::outbox;
+use crate::questions::LectureId;
+use chrono::naive::NaiveDateTime;
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::sha2::Sha256;
+
+/// What a student gets back after submitting: shown on the confirmation page and, if
+/// enabled, emailed to them.
+#[derive(Debug, Serialize)]
+pub(crate) struct Receipt {
+    pub class: String,
+    pub lec: LectureId,
+    pub user: String,
+    pub submitted_at: String,
+    pub answers: Vec<SubmittedAnswer>,
+    /// HMAC-SHA256 (keyed with the config secret) over the lecture, user, timestamp and
+    /// answers, so students can later show exactly what they submitted
+    pub hash: String,
+}
+
+/// Feeds a field into the MAC prefixed with its length, so that no two different sequences of
+/// fields produce the same input.
+fn input_field(mac: &mut Hmac<Sha256>, field: &str) {
+    mac.input(&(field.len() as u64).to_be_bytes());
+    mac.input(field.as_bytes());
+}
+
+impl Receipt {
+    pub(crate) fn new(
+        config: &Config,
+        num: LectureId,
+        user: &str,
+        submitted_at: NaiveDateTime,
+        answers: Vec<SubmittedAnswer>,
+    ) -> Receipt {
+        let submitted_at = submitted_at.format("%Y-%m-%d %H:%M:%S").to_string();
+        let mut mac = Hmac::new(Sha256::new(), config.secret.as_bytes());
+        input_field(&mut mac, &num.to_string());
+        input_field(&mut mac, user);
+        input_field(&mut mac, &submitted_at);
+        for a in &answers {
+            input_field(&mut mac, &a.q.to_string());
+            input_field(&mut mac, &a.answer);
+        }
+        let hash = mac
+            .result()
+            .code()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        Receipt {
+            class: config.class.clone(),
+            lec: num,
+            user: user.to_string(),
+            submitted_at: submitted_at,
+            answers: answers,
+            hash: hash,
+        }
+    }
+}
+
+/// Queues the receipt email to the student, if receipts are enabled.
+pub(crate) fn send(bg: &mut MySqlBackend, config: &Config, receipt: &Receipt) {
+    if config.send_emails && config.send_receipts {
+        outbox::enqueue(bg, vec![receipt.user.clone()], None, "receipt", receipt);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use chrono::NaiveDate;
+
+    fn receipt(config: &Config, answers: &[(u64, &str)]) -> Receipt {
+        Receipt::new(
+            config,
+            3,
+            "ada@example.com",
+            NaiveDate::from_ymd(2021, 9, 14).and_hms(10, 30, 0),
+            answers
+                .iter()
+                .map(|(q, a)| SubmittedAnswer {
+                    q: *q,
+                    answer: a.to_string(),
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn hash_is_stable_and_covers_the_answers() {
+        let config = config::parse("sample-config.toml").unwrap();
+        let hash = receipt(&config, &[(1, "ab"), (2, "c")]).hash;
+        assert_eq!(hash.len(), 64);
+        assert!(hash.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(receipt(&config, &[(1, "ab"), (2, "c")]).hash, hash);
+        assert_ne!(receipt(&config, &[(1, "ab"), (2, "d")]).hash, hash);
+        // moving text between answers changes the hash
+        assert_ne!(receipt(&config, &[(1, "a"), (2, "bc")]).hash, hash);
+    }
+
+    #[test]
+    fn hash_depends_on_the_secret() {
+        let config = config::parse("sample-config.toml").unwrap();
+        let mut other = config.clone();
+        other.secret.push('!');
+        assert_ne!(
+            receipt(&config, &[(1, "ab")]).hash,
+            receipt(&other, &[(1, "ab")]).hash
+        );
+    }
+}
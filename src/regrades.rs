@@ -0,0 +1,377 @@
+use crate::admin::Admin;
+use crate::apikey::ApiKey;
+use crate::backend::{MySqlBackend, Value};
+use crate::config::Config;
+use crate::grading;
+use crate::groups;
+use crate::outbox;
+use crate::questions::{LectureId, QuestionId};
+use chrono::naive::NaiveDateTime;
+use chrono::Local;
+use mysql::from_value;
+use rocket::form::Form;
+use rocket::response::status::BadRequest;
+use rocket::response::Redirect;
+use rocket::State;
+use rocket_dyn_templates::Template;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, FromForm)]
+pub(crate) struct RegradeRequestForm {
+    justification: String,
+}
+
+#[derive(Debug, FromForm)]
+pub(crate) struct RegradeResolutionForm {
+    response: String,
+    /// New score for the answer; leave empty to keep the current grade
+    score: Option<f64>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct Regrade {
+    pub id: u64,
+    pub email: String,
+    pub lec: LectureId,
+    pub q: QuestionId,
+    pub justification: String,
+    pub requested_at: NaiveDateTime,
+    pub open: bool,
+    pub response: Option<String>,
+    pub resolver: Option<String>,
+    pub resolved_at: Option<NaiveDateTime>,
+}
+
+#[derive(Serialize)]
+struct RegradeContext {
+    lec_id: LectureId,
+    q_id: QuestionId,
+    prompt: String,
+    answer: Option<String>,
+    grade: Option<f64>,
+    feedback: Option<String>,
+    requests: Vec<Regrade>,
+    can_request: bool,
+    parent: &'static str,
+}
+
+#[derive(Serialize)]
+struct RegradeQueueContext {
+    open: Vec<Regrade>,
+    resolved: Vec<Regrade>,
+    parent: &'static str,
+}
+
+/// Context of the regrade request and resolution emails.
+#[derive(Serialize)]
+struct RegradeEmail<'a> {
+    class: &'a str,
+    lec: LectureId,
+    q: QuestionId,
+    /// The student who requested the regrade
+    user: &'a str,
+    /// The justification or the staff response
+    text: &'a str,
+    /// The new score, if the resolution changed it
+    score: Option<String>,
+}
+
+fn regrade_from_row(r: &[Value]) -> Regrade {
+    let opt_string = |v: &Value| {
+        if *v == Value::NULL {
+            None
+        } else {
+            Some(from_value::<String>(v.clone()))
+        }
+    };
+    Regrade {
+        id: from_value(r[0].clone()),
+        email: from_value(r[1].clone()),
+        lec: from_value(r[2].clone()),
+        q: from_value(r[3].clone()),
+        justification: from_value(r[4].clone()),
+        requested_at: from_value(r[5].clone()),
+        open: r[6] == Value::NULL,
+        response: opt_string(&r[6]),
+        resolver: opt_string(&r[7]),
+        resolved_at: if r[8] == Value::NULL {
+            None
+        } else {
+            Some(from_value(r[8].clone()))
+        },
+    }
+}
+
+/// A student's regrade requests in a lecture, oldest first.
+pub(crate) fn user_requests(bg: &mut MySqlBackend, num: LectureId, email: &str) -> Vec<Regrade> {
+    bg.prep_exec(
+        "SELECT * FROM regrades WHERE lec = ? AND email = ? ORDER BY id",
+        vec![num.into(), email.into()],
+    )
+    .into_iter()
+    .map(|r| regrade_from_row(&r))
+    .collect()
+}
+
+#[get("/<num>/<qnum>")]
+pub(crate) fn regrade(
+    apikey: ApiKey,
+    num: LectureId,
+    qnum: QuestionId,
+    backend: &State<Arc<Mutex<MySqlBackend>>>,
+) -> Option<Template> {
+    let mut bg = backend.lock().unwrap();
+    let qres = bg.prep_exec(
+        "SELECT question FROM questions WHERE lec = ? AND q = ?",
+        vec![num.into(), qnum.into()],
+    );
+    let prompt: String = from_value(qres.get(0)?[0].clone());
+    let ares = bg.prep_exec(
+        "SELECT answer, score FROM answers WHERE lec = ? AND q = ? AND email = ?",
+        vec![num.into(), qnum.into(), apikey.user.clone().into()],
+    );
+    let gres = bg.prep_exec(
+        "SELECT score, comment FROM grades WHERE lec = ? AND q = ? AND email = ?",
+        vec![num.into(), qnum.into(), apikey.user.clone().into()],
+    );
+    let released = grading::grades_released(&mut bg, num);
+    let requests: Vec<_> = user_requests(&mut bg, num, &apikey.user)
+        .into_iter()
+        .filter(|r| r.q == qnum)
+        .collect();
+    drop(bg);
+
+    let answer: Option<String> = ares.get(0).map(|r| from_value(r[0].clone()));
+    let auto_score: Option<f64> = match ares.get(0) {
+        Some(r) if r[1] != Value::NULL => Some(from_value(r[1].clone())),
+        _ => None,
+    };
+    let (score, feedback) = match gres.get(0) {
+        Some(r) => (
+            if r[0] == Value::NULL {
+                None
+            } else {
+                Some(from_value::<f64>(r[0].clone()))
+            },
+            if r[1] == Value::NULL {
+                None
+            } else {
+                Some(from_value::<String>(r[1].clone()))
+            },
+        ),
+        None => (None, None),
+    };
+
+    let ctx = RegradeContext {
+        lec_id: num,
+        q_id: qnum,
+        prompt: prompt,
+        // only released grades can be disputed
+        can_request: released && answer.is_some() && !requests.iter().any(|r| r.open),
+        grade: if released { score.or(auto_score) } else { None },
+        feedback: if released {
+            feedback.filter(|f| !f.is_empty())
+        } else {
+            None
+        },
+        answer: answer,
+        requests: requests,
+        parent: "layout",
+    };
+    Some(Template::render("regrade", &ctx))
+}
+
+#[post("/<num>/<qnum>", data = "<data>")]
+pub(crate) fn request(
+    apikey: ApiKey,
+    num: LectureId,
+    qnum: QuestionId,
+    data: Form<RegradeRequestForm>,
+    backend: &State<Arc<Mutex<MySqlBackend>>>,
+    config: &State<Config>,
+) -> Result<Redirect, BadRequest<String>> {
+    if data.justification.trim().is_empty() {
+        return Err(BadRequest(Some(
+            "Please explain why the answer should be regraded.".into(),
+        )));
+    }
+    let mut bg = backend.lock().unwrap();
+    if !grading::grades_released(&mut bg, num) {
+        return Err(BadRequest(Some(
+            "Grades for this lecture have not been released.".into(),
+        )));
+    }
+    let answered = bg.prep_exec(
+        "SELECT q FROM answers WHERE lec = ? AND q = ? AND email = ?",
+        vec![num.into(), qnum.into(), apikey.user.clone().into()],
+    );
+    if answered.is_empty() {
+        return Err(BadRequest(Some("You did not answer this question.".into())));
+    }
+    if user_requests(&mut bg, num, &apikey.user)
+        .iter()
+        .any(|r| r.q == qnum && r.open)
+    {
+        return Err(BadRequest(Some(
+            "You already have an open regrade request for this question.".into(),
+        )));
+    }
+    bg.transaction(|bg| {
+        bg.insert(
+            "regrades",
+            vec![
+                Value::NULL,
+                apikey.user.clone().into(),
+                num.into(),
+                qnum.into(),
+                data.justification.clone().into(),
+                Local::now().naive_local().into(),
+                Value::NULL,
+                Value::NULL,
+                Value::NULL,
+            ],
+        )
+    })
+    .map_err(|e| BadRequest(Some(format!("Storing the regrade request failed: {}", e))))?;
+
+    if config.send_emails {
+        let mut recipients = config.staff.clone();
+        recipients.push(apikey.user.clone());
+        outbox::enqueue(
+            &mut bg,
+            recipients,
+            Some(&apikey.user),
+            "regrade_request",
+            &RegradeEmail {
+                class: &config.class,
+                lec: num,
+                q: qnum,
+                user: &apikey.user,
+                text: &data.justification,
+                score: None,
+            },
+        );
+    }
+    drop(bg);
+
+    Ok(Redirect::to(format!("/regrades/{}/{}", num, qnum)))
+}
+
+#[get("/")]
+pub(crate) fn queue(_adm: Admin, backend: &State<Arc<Mutex<MySqlBackend>>>) -> Template {
+    let mut bg = backend.lock().unwrap();
+    let res = bg.prep_exec("SELECT * FROM regrades ORDER BY id", vec![]);
+    drop(bg);
+
+    let (open, mut resolved): (Vec<_>, Vec<_>) = res
+        .into_iter()
+        .map(|r| regrade_from_row(&r))
+        .partition(|r| r.open);
+    // most recently resolved first
+    resolved.reverse();
+
+    let ctx = RegradeQueueContext {
+        open: open,
+        resolved: resolved,
+        parent: "layout",
+    };
+    Template::render("admin/regrades", &ctx)
+}
+
+#[post("/<id>", data = "<data>")]
+pub(crate) fn resolve(
+    _adm: Admin,
+    apikey: ApiKey,
+    id: u64,
+    data: Form<RegradeResolutionForm>,
+    backend: &State<Arc<Mutex<MySqlBackend>>>,
+    config: &State<Config>,
+) -> Result<Option<Redirect>, BadRequest<String>> {
+    let mut bg = backend.lock().unwrap();
+    let res = bg.prep_exec(
+        "SELECT * FROM regrades WHERE id = ? AND response IS NULL",
+        vec![id.into()],
+    );
+    let regrade = match res.get(0) {
+        Some(r) => regrade_from_row(r),
+        None => return Ok(None),
+    };
+    let ts: Value = Local::now().naive_local().into();
+    bg.transaction(|bg| {
+        bg.prep_exec(
+            "UPDATE regrades SET response = ?, resolver = ?, resolved_at = ? WHERE id = ?",
+            vec![
+                data.response.clone().into(),
+                apikey.user.clone().into(),
+                ts.clone(),
+                id.into(),
+            ],
+        );
+
+        if let Some(score) = data.score {
+            let num = regrade.lec;
+            // group members share their answer's grade
+            for member in groups::members(bg, num, &regrade.email) {
+                let updated = bg.prep_exec(
+                    "SELECT email FROM grades WHERE email = ? AND lec = ? AND q = ?",
+                    vec![member.clone().into(), regrade.lec.into(), regrade.q.into()],
+                );
+                if updated.is_empty() {
+                    bg.insert(
+                        "grades",
+                        vec![
+                            member.into(),
+                            regrade.lec.into(),
+                            regrade.q.into(),
+                            score.into(),
+                            "".into(),
+                            apikey.user.clone().into(),
+                            ts.clone(),
+                        ],
+                    );
+                } else {
+                    bg.prep_exec(
+                        "UPDATE grades SET score = ?, grader = ?, graded_at = ? \
+                         WHERE email = ? AND lec = ? AND q = ?",
+                        vec![
+                            score.into(),
+                            apikey.user.clone().into(),
+                            ts.clone(),
+                            member.into(),
+                            regrade.lec.into(),
+                            regrade.q.into(),
+                        ],
+                    );
+                }
+            }
+        }
+    })
+    .map_err(|e| {
+        BadRequest(Some(format!(
+            "Resolving regrade request {} failed: {}",
+            id, e
+        )))
+    })?;
+
+    if config.send_emails {
+        let mut recipients = config.staff.clone();
+        recipients.push(regrade.email.clone());
+        outbox::enqueue(
+            &mut bg,
+            recipients,
+            Some(&apikey.user),
+            "regrade_resolved",
+            &RegradeEmail {
+                class: &config.class,
+                lec: regrade.lec,
+                q: regrade.q,
+                user: &regrade.email,
+                text: &data.response,
+                score: data.score.map(|s| s.to_string()),
+            },
+        );
+    }
+    drop(bg);
+
+    Ok(Some(Redirect::to("/admin/regrades")))
+}
@@ -0,0 +1,381 @@
+use crate::admin::Admin;
+use crate::apikey;
+use crate::backend::{MySqlBackend, Value};
+use crate::config::Config;
+use crate::csv;
+use mysql::from_value;
+use rocket::form::Form;
+use rocket::fs::TempFile;
+use rocket::State;
+use rocket_dyn_templates::Template;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+/// Header names (lowercased) recognized for each roster column; the first match wins.
+const EMAIL_HEADERS: [&str; 3] = ["email", "email address", "e-mail"];
+const NAME_HEADERS: [&str; 3] = ["name", "student name", "student"];
+const SIS_ID_HEADERS: [&str; 4] = ["sis user id", "student id", "sis id", "id"];
+
+/// An enrolled student.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub(crate) struct Student {
+    pub email: String,
+    pub name: Option<String>,
+    pub sis_id: Option<String>,
+}
+
+#[derive(Debug, FromForm)]
+pub(crate) struct RosterForm<'r> {
+    file: TempFile<'r>,
+    create_users: bool,
+    send_keys: bool,
+    dry_run: bool,
+}
+
+/// What importing a roster changed (or would change, in a dry run).
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct RosterReport {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub unchanged: Vec<String>,
+    /// Students for whom a `users` row (and so an API key) was created
+    pub created_users: Vec<String>,
+    /// Students whose API key email was queued
+    pub emailed: Vec<String>,
+    pub errors: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct RosterContext {
+    report: Option<RosterReport>,
+    /// Whether the report lists any students, i.e., the roster was valid
+    changed: bool,
+    applied: bool,
+    num_unchanged: usize,
+    num_students: usize,
+    students: Vec<Student>,
+    parent: &'static str,
+}
+
+fn find_column(header: &[String], names: &[&str]) -> Option<usize> {
+    names
+        .iter()
+        .find_map(|n| header.iter().position(|h| h.trim().to_lowercase() == *n))
+}
+
+fn non_empty(record: &[String], col: Option<usize>) -> Option<String> {
+    col.and_then(|i| record.get(i))
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+/// Parses a registrar CSV export. The first row must be a header with an email column; name and
+/// SIS ID columns are optional. Students listed more than once are only kept once.
+pub(crate) fn parse(text: &str) -> Result<Vec<Student>, String> {
+    let mut records = csv::parse(text).into_iter();
+    let header = records
+        .next()
+        .ok_or_else(|| String::from("The roster is empty"))?;
+    let email_col = find_column(&header, &EMAIL_HEADERS)
+        .ok_or_else(|| String::from("The roster has no email column"))?;
+    let name_col = find_column(&header, &NAME_HEADERS);
+    let sis_id_col = find_column(&header, &SIS_ID_HEADERS);
+
+    let mut seen = HashSet::new();
+    let mut students = vec![];
+    // line 1 is the header
+    for (i, r) in records.enumerate() {
+        let email = non_empty(&r, Some(email_col))
+            .ok_or_else(|| format!("Line {}: missing email address", i + 2))?;
+        if !email.contains('@') {
+            return Err(format!("Line {}: invalid email address {}", i + 2, email));
+        }
+        if !seen.insert(email.clone()) {
+            continue;
+        }
+        students.push(Student {
+            email: email,
+            name: non_empty(&r, name_col),
+            sis_id: non_empty(&r, sis_id_col),
+        });
+    }
+    if students.is_empty() {
+        return Err(String::from("The roster lists no students"));
+    }
+    Ok(students)
+}
+
+fn enrolled(bg: &mut MySqlBackend) -> Vec<Student> {
+    let opt = |v: &Value| {
+        if *v == Value::NULL {
+            None
+        } else {
+            Some(from_value::<String>(v.clone()))
+        }
+    };
+    bg.prep_exec(
+        "SELECT email, name, sis_id FROM enrollment ORDER BY email",
+        vec![],
+    )
+    .into_iter()
+    .map(|r| Student {
+        email: from_value(r[0].clone()),
+        name: opt(&r[1]),
+        sis_id: opt(&r[2]),
+    })
+    .collect()
+}
+
+/// The students taking the course: the enrollment roster if one was imported, otherwise all
+/// registered users other than staff and admins.
+pub(crate) fn students(bg: &mut MySqlBackend, config: &Config) -> Vec<Student> {
+    let roster = enrolled(bg);
+    if !roster.is_empty() {
+        return roster;
+    }
+    let emails: HashSet<String> = bg
+        .prep_exec("SELECT email FROM users", vec![])
+        .into_iter()
+        .map(|r| from_value::<String>(r[0].clone()))
+        .filter(|e| !config.is_staff(e))
+        .collect();
+    let mut students: Vec<Student> = emails
+        .into_iter()
+        .map(|e| Student {
+            email: e,
+            name: None,
+            sis_id: None,
+        })
+        .collect();
+    students.sort_by(|a, b| a.email.cmp(&b.email));
+    students
+}
+
+/// Replaces the enrollment with `roster`, optionally creating `users` rows for students who do
+/// not have an API key yet and emailing them their new key. Students dropped from the roster
+/// keep their users row and answers.
+pub(crate) fn run(
+    bg: &mut MySqlBackend,
+    config: &Config,
+    roster: &[Student],
+    create_users: bool,
+    send_keys: bool,
+    dry_run: bool,
+) -> RosterReport {
+    let current: BTreeMap<String, Student> = enrolled(bg)
+        .into_iter()
+        .map(|s| (s.email.clone(), s))
+        .collect();
+    let listed: HashSet<&str> = roster.iter().map(|s| s.email.as_str()).collect();
+    let users: HashSet<String> = bg
+        .prep_exec("SELECT email FROM users", vec![])
+        .into_iter()
+        .map(|r| from_value(r[0].clone()))
+        .collect();
+
+    let mut report = RosterReport::default();
+    for s in roster {
+        if current.contains_key(&s.email) {
+            report.unchanged.push(s.email.clone());
+        } else {
+            report.added.push(s.email.clone());
+        }
+        if create_users && !users.contains(&s.email) {
+            report.created_users.push(s.email.clone());
+        }
+    }
+    report.removed = current
+        .keys()
+        .filter(|e| !listed.contains(e.as_str()))
+        .cloned()
+        .collect();
+    if send_keys && !config.send_emails {
+        report.errors.push(String::from(
+            "Emails are disabled in the configuration, no keys were sent",
+        ));
+    }
+    if dry_run {
+        return report;
+    }
+
+    let res = bg.transaction(|bg| {
+        for email in &report.removed {
+            bg.prep_exec(
+                "DELETE FROM enrollment WHERE email = ?",
+                vec![email.as_str().into()],
+            );
+        }
+        for s in roster {
+            bg.replace(
+                "enrollment",
+                vec![
+                    s.email.as_str().into(),
+                    s.name.clone().map(Value::from).unwrap_or(Value::NULL),
+                    s.sis_id.clone().map(Value::from).unwrap_or(Value::NULL),
+                ],
+            );
+        }
+        for email in &report.created_users {
+            let key = apikey::generate_key(config, email);
+            let is_admin = if config.admins.contains(email) { 1 } else { 0 };
+            bg.insert(
+                "users",
+                vec![email.as_str().into(), key.into(), is_admin.into()],
+            );
+        }
+    });
+    if let Err(e) = res {
+        report.errors.push(format!(
+            "Updating the roster failed, nothing was changed: {}",
+            e
+        ));
+        return report;
+    }
+
+    // only queue emails once the keys are committed
+    if send_keys && config.send_emails {
+        for email in report.created_users.clone() {
+            let key = apikey::generate_key(config, &email);
+            apikey::send_key(bg, config, &email, &key);
+            report.emailed.push(email);
+        }
+    }
+    report
+}
+
+/// Imports a roster CSV file from disk, e.g., from the command line.
+pub(crate) fn import_file(
+    bg: &mut MySqlBackend,
+    config: &Config,
+    path: &str,
+    create_users: bool,
+    send_keys: bool,
+    dry_run: bool,
+) -> Result<RosterReport, String> {
+    let content = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let roster = parse(&content)?;
+    Ok(run(bg, config, &roster, create_users, send_keys, dry_run))
+}
+
+/// Reads the contents of an uploaded file.
+async fn read_upload(file: &mut TempFile<'_>) -> std::io::Result<String> {
+    let tmp = std::env::temp_dir().join(format!(".roster-{:016x}", rand::random::<u64>()));
+    file.copy_to(&tmp).await?;
+    let content = rocket::tokio::fs::read_to_string(&tmp).await;
+    rocket::tokio::fs::remove_file(&tmp).await?;
+    content
+}
+
+#[get("/")]
+pub(crate) fn roster(_adm: Admin, backend: &State<Arc<Mutex<MySqlBackend>>>) -> Template {
+    let mut bg = backend.lock().unwrap();
+    let students = enrolled(&mut bg);
+    drop(bg);
+
+    let ctx = RosterContext {
+        report: None,
+        changed: false,
+        applied: false,
+        num_unchanged: 0,
+        num_students: students.len(),
+        students: students,
+        parent: "layout",
+    };
+    Template::render("admin/roster", &ctx)
+}
+
+#[post("/", data = "<data>")]
+pub(crate) async fn roster_submit(
+    _adm: Admin,
+    mut data: Form<RosterForm<'_>>,
+    backend: &State<Arc<Mutex<MySqlBackend>>>,
+    config: &State<Config>,
+) -> Template {
+    let res = match read_upload(&mut data.file).await {
+        Ok(content) => parse(&content),
+        Err(e) => Err(format!("Failed to read the uploaded roster: {}", e)),
+    };
+
+    let mut bg = backend.lock().unwrap();
+    let report = match res {
+        Ok(roster) => run(
+            &mut bg,
+            config,
+            &roster,
+            data.create_users,
+            data.send_keys,
+            data.dry_run,
+        ),
+        Err(e) => RosterReport {
+            errors: vec![e],
+            ..RosterReport::default()
+        },
+    };
+    let students = enrolled(&mut bg);
+    drop(bg);
+
+    let changed =
+        !report.added.is_empty() || !report.removed.is_empty() || !report.unchanged.is_empty();
+    let ctx = RosterContext {
+        changed: changed,
+        applied: changed && !data.dry_run,
+        num_unchanged: report.unchanged.len(),
+        num_students: students.len(),
+        report: Some(report),
+        students: students,
+        parent: "layout",
+    };
+    Template::render("admin/roster", &ctx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_finds_columns_by_header() {
+        let students = parse(
+            "SIS User ID,Student Name,E-mail\n\
+             123,Ada Lovelace,ada@example.com\n\
+             ,,bob@example.com\n",
+        )
+        .unwrap();
+        assert_eq!(
+            students,
+            vec![
+                Student {
+                    email: "ada@example.com".into(),
+                    name: Some("Ada Lovelace".into()),
+                    sis_id: Some("123".into()),
+                },
+                Student {
+                    email: "bob@example.com".into(),
+                    name: None,
+                    sis_id: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_keeps_duplicates_once() {
+        let students = parse("email,name\nada@example.com,Ada\nada@example.com,Ada L.\n").unwrap();
+        assert_eq!(students.len(), 1);
+        assert_eq!(students[0].name.as_deref(), Some("Ada"));
+    }
+
+    #[test]
+    fn parse_rejects_bad_rosters() {
+        assert!(parse("").is_err());
+        assert!(parse("name,id\nAda,123\n").is_err());
+        assert!(parse("email\n").is_err());
+        assert_eq!(
+            parse("email\nada@example.com\nnot-an-address\n"),
+            Err(String::from("Line 3: invalid email address not-an-address"))
+        );
+        assert_eq!(
+            parse("email,name\n,Ada\n"),
+            Err(String::from("Line 2: missing email address"))
+        );
+    }
+}
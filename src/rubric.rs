@@ -0,0 +1,208 @@
+use crate::admin::Admin;
+use crate::backend::{MySqlBackend, Value};
+use crate::questions::{LectureId, QuestionId};
+use mysql::from_value;
+use rocket::form::Form;
+use rocket::response::Redirect;
+use rocket::State;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, FromForm)]
+pub(crate) struct RubricItemForm {
+    points: f64,
+    description: String,
+}
+
+#[derive(Serialize)]
+pub(crate) struct RubricItem {
+    pub id: u64,
+    pub points: f64,
+    pub description: String,
+    pub applied: bool,
+}
+
+/// The rubric items defined for a question, in the order they were added.
+pub(crate) fn items(bg: &mut MySqlBackend, lec: LectureId, q: QuestionId) -> Vec<RubricItem> {
+    bg.prep_exec(
+        "SELECT id, points, description FROM rubric_items WHERE lec = ? AND q = ? ORDER BY id",
+        vec![lec.into(), q.into()],
+    )
+    .into_iter()
+    .map(|r| RubricItem {
+        id: from_value(r[0].clone()),
+        points: from_value(r[1].clone()),
+        description: from_value(r[2].clone()),
+        applied: false,
+    })
+    .collect()
+}
+
+/// The rubric items of all questions in a lecture, keyed by question.
+pub(crate) fn lecture_items(
+    bg: &mut MySqlBackend,
+    lec: LectureId,
+) -> HashMap<QuestionId, Vec<RubricItem>> {
+    let mut items = HashMap::new();
+    for r in bg.prep_exec(
+        "SELECT q, id, points, description FROM rubric_items WHERE lec = ? ORDER BY id",
+        vec![lec.into()],
+    ) {
+        let q: u64 = from_value(r[0].clone());
+        items.entry(q).or_insert_with(Vec::new).push(RubricItem {
+            id: from_value(r[1].clone()),
+            points: from_value(r[2].clone()),
+            description: from_value(r[3].clone()),
+            applied: false,
+        });
+    }
+    items
+}
+
+/// The ids of the rubric items applied to each of a student's answers in a lecture, keyed by
+/// question.
+pub(crate) fn applied(
+    bg: &mut MySqlBackend,
+    lec: LectureId,
+    email: &str,
+) -> HashMap<QuestionId, Vec<u64>> {
+    let mut applied = HashMap::new();
+    for r in bg.prep_exec(
+        "SELECT q, item FROM rubric_applied WHERE lec = ? AND email = ?",
+        vec![lec.into(), email.into()],
+    ) {
+        let q: u64 = from_value(r[0].clone());
+        let item: u64 = from_value(r[1].clone());
+        applied.entry(q).or_insert_with(Vec::new).push(item);
+    }
+    applied
+}
+
+/// Replaces the rubric items applied to a student's answer. Returns the resulting score, or
+/// `None` if no items are applied (in which case the grader's score stands).
+pub(crate) fn apply(
+    bg: &mut MySqlBackend,
+    lec: LectureId,
+    q: u64,
+    email: &str,
+    item_ids: &[u64],
+) -> Option<f64> {
+    bg.prep_exec(
+        "DELETE FROM rubric_applied WHERE lec = ? AND q = ? AND email = ?",
+        vec![lec.into(), q.into(), email.into()],
+    );
+    let items = items(bg, lec, q);
+    let mut score = None;
+    for item in items.iter().filter(|i| item_ids.contains(&i.id)) {
+        bg.insert(
+            "rubric_applied",
+            vec![email.into(), lec.into(), q.into(), item.id.into()],
+        );
+        score = Some(score.unwrap_or(0.0) + item.points);
+    }
+    score
+}
+
+/// Recomputes the scores of all answers to a question that were graded with its rubric, after
+/// the rubric changed. Students listed in `affected` whose last applied item was removed get a
+/// score of zero.
+fn recompute(bg: &mut MySqlBackend, lec: LectureId, q: QuestionId, affected: &[String]) {
+    let res = bg.prep_exec(
+        "SELECT rubric_applied.email, SUM(rubric_items.points) \
+         FROM rubric_applied JOIN rubric_items ON (rubric_applied.item = rubric_items.id) \
+         WHERE rubric_applied.lec = ? AND rubric_applied.q = ? \
+         GROUP BY rubric_applied.email",
+        vec![lec.into(), q.into()],
+    );
+    let mut scores: HashMap<String, f64> = affected.iter().map(|e| (e.clone(), 0.0)).collect();
+    for r in res {
+        scores.insert(from_value(r[0].clone()), from_value(r[1].clone()));
+    }
+    for (email, score) in scores {
+        bg.prep_exec(
+            "UPDATE grades SET score = ? WHERE email = ? AND lec = ? AND q = ?",
+            vec![score.into(), email.into(), lec.into(), q.into()],
+        );
+    }
+}
+
+#[post("/rubric/<num>/<qnum>", data = "<data>")]
+pub(crate) fn add_item(
+    _adm: Admin,
+    num: LectureId,
+    qnum: QuestionId,
+    data: Form<RubricItemForm>,
+    backend: &State<Arc<Mutex<MySqlBackend>>>,
+) -> Redirect {
+    let mut bg = backend.lock().unwrap();
+    bg.insert(
+        "rubric_items",
+        vec![
+            Value::NULL,
+            num.into(),
+            qnum.into(),
+            data.points.into(),
+            data.description.clone().into(),
+        ],
+    );
+    drop(bg);
+
+    Redirect::to(format!("/admin/lec/{}/{}", num, qnum))
+}
+
+#[post("/rubric/<num>/<qnum>/<item>", data = "<data>")]
+pub(crate) fn edit_item(
+    _adm: Admin,
+    num: LectureId,
+    qnum: QuestionId,
+    item: u64,
+    data: Form<RubricItemForm>,
+    backend: &State<Arc<Mutex<MySqlBackend>>>,
+) -> Redirect {
+    let mut bg = backend.lock().unwrap();
+    bg.prep_exec(
+        "UPDATE rubric_items SET points = ?, description = ? WHERE id = ? AND lec = ? AND q = ?",
+        vec![
+            data.points.into(),
+            data.description.clone().into(),
+            item.into(),
+            num.into(),
+            qnum.into(),
+        ],
+    );
+    recompute(&mut bg, num, qnum, &[]);
+    drop(bg);
+
+    Redirect::to(format!("/admin/lec/{}/{}", num, qnum))
+}
+
+#[post("/rubric/<num>/<qnum>/<item>/delete")]
+pub(crate) fn delete_item(
+    _adm: Admin,
+    num: LectureId,
+    qnum: QuestionId,
+    item: u64,
+    backend: &State<Arc<Mutex<MySqlBackend>>>,
+) -> Redirect {
+    let mut bg = backend.lock().unwrap();
+    let affected: Vec<String> = bg
+        .prep_exec(
+            "SELECT email FROM rubric_applied WHERE item = ? AND lec = ? AND q = ?",
+            vec![item.into(), num.into(), qnum.into()],
+        )
+        .into_iter()
+        .map(|r| from_value(r[0].clone()))
+        .collect();
+    bg.prep_exec(
+        "DELETE FROM rubric_applied WHERE item = ? AND lec = ? AND q = ?",
+        vec![item.into(), num.into(), qnum.into()],
+    );
+    bg.prep_exec(
+        "DELETE FROM rubric_items WHERE id = ? AND lec = ? AND q = ?",
+        vec![item.into(), num.into(), qnum.into()],
+    );
+    recompute(&mut bg, num, qnum, &affected);
+    drop(bg);
+
+    Redirect::to(format!("/admin/lec/{}/{}", num, qnum))
+}
@@ -1,6 +1,22 @@
 CREATE TABLE users (email varchar(255), apikey varchar(255), is_admin tinyint, PRIMARY KEY (apikey));
-CREATE TABLE lectures (id int, label varchar(255), PRIMARY KEY (id));
-CREATE TABLE questions (lec int, q int, question text, PRIMARY KEY (lec, q));
-CREATE TABLE answers (email varchar(255), lec int, q int, answer text, submitted_at datetime, PRIMARY KEY (email, lec, q));
+CREATE TABLE lectures (id bigint unsigned, label varchar(255), grades_released tinyint, blind_grading tinyint, deadline datetime, PRIMARY KEY (id));
+CREATE TABLE questions (lec bigint unsigned, q bigint unsigned, question text, qtype varchar(16), options text, tolerance double, answer_key text, points double, position int, PRIMARY KEY (lec, q));
+CREATE TABLE answers (email varchar(255), lec bigint unsigned, q bigint unsigned, answer text, submitted_at datetime, score double, PRIMARY KEY (email, lec, q));
+CREATE TABLE grades (email varchar(255), lec bigint unsigned, q bigint unsigned, score double, comment text, grader varchar(255), graded_at datetime, PRIMARY KEY (email, lec, q));
+CREATE TABLE deanonymizations (admin varchar(255), lec bigint unsigned, pseudonym varchar(255), email varchar(255), at datetime);
+CREATE TABLE rubric_items (id int NOT NULL AUTO_INCREMENT, lec bigint unsigned, q bigint unsigned, points double, description text, PRIMARY KEY (id));
+CREATE TABLE rubric_applied (email varchar(255), lec bigint unsigned, q bigint unsigned, item int, PRIMARY KEY (email, lec, q, item));
+CREATE TABLE uploads (email varchar(255), lec bigint unsigned, q bigint unsigned, filename varchar(255), content_type varchar(255), size bigint, sha256 varchar(64), PRIMARY KEY (email, lec, q));
+CREATE TABLE peer_assignments (lec bigint unsigned, reviewer varchar(255), author varchar(255), PRIMARY KEY (lec, reviewer, author));
+CREATE TABLE peer_reviews (lec bigint unsigned, reviewer varchar(255), author varchar(255), q bigint unsigned, review text, submitted_at datetime, PRIMARY KEY (lec, reviewer, author, q));
+CREATE TABLE lec_groups (id int NOT NULL AUTO_INCREMENT, lec bigint unsigned, name varchar(255), PRIMARY KEY (id));
+CREATE TABLE group_members (group_id int, email varchar(255), accepted tinyint, PRIMARY KEY (group_id, email));
+CREATE TABLE regrades (id int NOT NULL AUTO_INCREMENT, email varchar(255), lec bigint unsigned, q bigint unsigned, justification text, requested_at datetime, response text, resolver varchar(255), resolved_at datetime, PRIMARY KEY (id));
+CREATE TABLE enrollment (email varchar(255), name varchar(255), sis_id varchar(255), PRIMARY KEY (email));
+CREATE TABLE lec_notifications (lec bigint unsigned, enabled tinyint, mode varchar(16), recipients text, PRIMARY KEY (lec));
+CREATE TABLE outbox (id int NOT NULL AUTO_INCREMENT, recipients text, reply_to varchar(255), template varchar(255), context text, attempts int, next_attempt datetime, last_error text, sent_at datetime, PRIMARY KEY (id));
+CREATE TABLE notification_digest (id int NOT NULL AUTO_INCREMENT, lec bigint unsigned, email varchar(255), submitted_at datetime, answers text, PRIMARY KEY (id));
 
 CREATE VIEW lec_qcount as SELECT questions.lec, COUNT(questions.q) AS qcount FROM questions GROUP BY questions.lec;
+CREATE VIEW lec_maxscore as SELECT questions.lec, SUM(questions.points) AS maxscore FROM questions WHERE questions.answer_key IS NOT NULL GROUP BY questions.lec;
+CREATE VIEW lec_answered as SELECT answers.email, answers.lec, SUM(answers.answer <> '') AS answered, MAX(answers.submitted_at) AS last_submitted FROM answers GROUP BY answers.email, answers.lec;
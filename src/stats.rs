@@ -0,0 +1,218 @@
+use crate::admin::Admin;
+use crate::backend::{MySqlBackend, Value};
+use crate::config::Config;
+use crate::questions;
+use crate::roster;
+use chrono::naive::{NaiveDate, NaiveDateTime};
+use mysql::from_value;
+use rocket::http::ContentType;
+use rocket::State;
+use rocket_dyn_templates::Template;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+/// Hours before and after a lecture's deadline covered by the hourly submission counts.
+const HOURS_BEFORE_DEADLINE: i64 = 48;
+const HOURS_AFTER_DEADLINE: i64 = 24;
+
+#[derive(Serialize)]
+struct DailyCount {
+    day: NaiveDate,
+    submissions: u64,
+}
+
+#[derive(Serialize)]
+struct HourlyCount {
+    /// Hours relative to the deadline (e.g., -1 is the hour before it)
+    hour: i64,
+    submissions: u64,
+}
+
+#[derive(Serialize)]
+struct LectureStats {
+    id: u64,
+    label: String,
+    num_qs: u64,
+    respondents: u64,
+    response_rate: Option<f64>,
+    median_length: Option<f64>,
+    deadline: Option<NaiveDateTime>,
+    daily: Vec<DailyCount>,
+    around_deadline: Vec<HourlyCount>,
+}
+
+#[derive(Serialize)]
+struct Stats {
+    enrolled: u64,
+    lectures: Vec<LectureStats>,
+}
+
+#[derive(Serialize)]
+struct StatsContext {
+    stats: Stats,
+    parent: &'static str,
+}
+
+fn median(mut values: Vec<usize>) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort();
+    let mid = values.len() / 2;
+    if values.len() % 2 == 0 {
+        Some((values[mid - 1] + values[mid]) as f64 / 2.0)
+    } else {
+        Some(values[mid] as f64)
+    }
+}
+
+/// Computes participation statistics. Students are the enrolled students if a roster was
+/// imported, otherwise all registered users other than staff and admins. A submission is one
+/// press of the submit button, i.e., a student's answers in a lecture that share a timestamp;
+/// only the latest submission of each answer is kept, so resubmissions move a student's earlier
+/// answers forward in time.
+fn compute(bg: &mut MySqlBackend, config: &Config) -> Stats {
+    let lres = bg.prep_exec(
+        "SELECT lectures.id, lectures.label, lectures.deadline, lec_qcount.qcount \
+         FROM lectures LEFT JOIN lec_qcount ON (lectures.id = lec_qcount.lec) ORDER BY lectures.id",
+        vec![],
+    );
+    let ares = bg.prep_exec(
+        "SELECT email, lec, answer, submitted_at FROM answers",
+        vec![],
+    );
+
+    let students: HashSet<String> = roster::students(bg, config)
+        .into_iter()
+        .map(|s| s.email)
+        .collect();
+    let enrolled = students.len() as u64;
+
+    let mut respondents: HashMap<u64, HashSet<String>> = HashMap::new();
+    let mut lengths: HashMap<u64, Vec<usize>> = HashMap::new();
+    let mut submissions: HashMap<u64, HashSet<(String, NaiveDateTime)>> = HashMap::new();
+    for r in ares {
+        let email: String = from_value(r[0].clone());
+        if !students.contains(&email) {
+            continue;
+        }
+        let lec: u64 = from_value(r[1].clone());
+        let answer: String = from_value(r[2].clone());
+        // a blank answer is a question the student left out
+        if answer.trim().is_empty() {
+            continue;
+        }
+        lengths.entry(lec).or_default().push(answer.chars().count());
+        if let Some(ts) = questions::opt_datetime(&r[3]) {
+            submissions
+                .entry(lec)
+                .or_default()
+                .insert((email.clone(), ts));
+        }
+        respondents.entry(lec).or_default().insert(email);
+    }
+
+    let lectures = lres
+        .into_iter()
+        .map(|r| {
+            let id: u64 = from_value(r[0].clone());
+            let deadline = questions::opt_datetime(&r[2]);
+            let num_respondents = respondents.get(&id).map(|s| s.len()).unwrap_or(0) as u64;
+
+            let mut daily = BTreeMap::new();
+            let mut hourly = BTreeMap::new();
+            for (_, ts) in submissions.remove(&id).unwrap_or_default() {
+                *daily.entry(ts.date()).or_insert(0u64) += 1;
+                if let Some(deadline) = deadline {
+                    let hour = (ts - deadline).num_minutes().div_euclid(60);
+                    if hour >= -HOURS_BEFORE_DEADLINE && hour < HOURS_AFTER_DEADLINE {
+                        *hourly.entry(hour).or_insert(0u64) += 1;
+                    }
+                }
+            }
+
+            LectureStats {
+                id: id,
+                label: from_value(r[1].clone()),
+                num_qs: if r[3] == Value::NULL {
+                    0
+                } else {
+                    from_value(r[3].clone())
+                },
+                respondents: num_respondents,
+                response_rate: if enrolled > 0 {
+                    Some(100.0 * num_respondents as f64 / enrolled as f64)
+                } else {
+                    None
+                },
+                median_length: median(lengths.remove(&id).unwrap_or_default()),
+                deadline: deadline,
+                daily: daily
+                    .into_iter()
+                    .map(|(day, submissions)| DailyCount {
+                        day: day,
+                        submissions: submissions,
+                    })
+                    .collect(),
+                around_deadline: hourly
+                    .into_iter()
+                    .map(|(hour, submissions)| HourlyCount {
+                        hour: hour,
+                        submissions: submissions,
+                    })
+                    .collect(),
+            }
+        })
+        .collect();
+
+    Stats {
+        enrolled: enrolled,
+        lectures: lectures,
+    }
+}
+
+#[get("/")]
+pub(crate) fn stats(
+    _adm: Admin,
+    backend: &State<Arc<Mutex<MySqlBackend>>>,
+    config: &State<Config>,
+) -> Template {
+    let mut bg = backend.lock().unwrap();
+    let stats = compute(&mut bg, config);
+    drop(bg);
+
+    let ctx = StatsContext {
+        stats: stats,
+        parent: "layout",
+    };
+    Template::render("admin/stats", &ctx)
+}
+
+#[get("/json")]
+pub(crate) fn stats_json(
+    _adm: Admin,
+    backend: &State<Arc<Mutex<MySqlBackend>>>,
+    config: &State<Config>,
+) -> (ContentType, String) {
+    let mut bg = backend.lock().unwrap();
+    let stats = compute(&mut bg, config);
+    drop(bg);
+
+    (
+        ContentType::JSON,
+        serde_json::to_string(&stats).expect("failed to serialize stats"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn median_of_odd_and_even_counts() {
+        assert_eq!(median(vec![]), None);
+        assert_eq!(median(vec![7]), Some(7.0));
+        assert_eq!(median(vec![5, 1, 3]), Some(3.0));
+        assert_eq!(median(vec![4, 1, 2, 3]), Some(2.5));
+    }
+}
@@ -0,0 +1,138 @@
+use crate::admin::Admin;
+use crate::backend::MySqlBackend;
+use crate::config::Config;
+use crate::grading;
+use crate::questions::{LectureId, QuestionId};
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use mysql::from_value;
+use rocket::fs::{NamedFile, TempFile};
+use rocket::http::{ContentType, Header};
+use rocket::State;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// An uploaded answer file, as recorded in the `uploads` table.
+pub(crate) struct StoredFile {
+    pub filename: String,
+    pub content_type: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// A stored file, sent to the browser as an attachment.
+#[derive(Responder)]
+pub(crate) struct Download {
+    file: NamedFile,
+    content_type: ContentType,
+    disposition: Header<'static>,
+}
+
+/// The file name as given by the browser, without any directory components.
+fn original_name(file: &TempFile<'_>) -> Option<String> {
+    let raw = file.raw_name()?.dangerous_unsafe_unsanitized_raw().as_str();
+    Path::new(raw)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .map(String::from)
+}
+
+/// Location of the file with the given content hash. Files are stored by hash, so identical
+/// uploads share storage.
+fn stored_path(config: &Config, sha256: &str) -> PathBuf {
+    Path::new(&config.upload_dir).join(sha256)
+}
+
+/// Checks an uploaded file against the size limit and the accepted file extensions. The
+/// extensions are the question's own list if it has one, otherwise those in the config.
+pub(crate) fn check(
+    config: &Config,
+    extensions: &[String],
+    file: &TempFile<'_>,
+) -> Result<(), String> {
+    if file.len() > config.upload_max_size {
+        return Err(format!(
+            "file is larger than the limit of {} bytes",
+            config.upload_max_size
+        ));
+    }
+    let allowed = if extensions.is_empty() {
+        &config.upload_types[..]
+    } else {
+        extensions
+    };
+    if allowed.is_empty() {
+        return Ok(());
+    }
+    let name = original_name(file).unwrap_or_default().to_lowercase();
+    if allowed
+        .iter()
+        .any(|ext| name.ends_with(&format!(".{}", ext.trim_start_matches('.').to_lowercase())))
+    {
+        Ok(())
+    } else {
+        Err(format!("only {} files are accepted", allowed.join(", ")))
+    }
+}
+
+/// Copies an uploaded file into the upload directory and computes its hash.
+pub(crate) async fn store(config: &Config, file: &mut TempFile<'_>) -> std::io::Result<StoredFile> {
+    let dir = Path::new(&config.upload_dir);
+    rocket::tokio::fs::create_dir_all(dir).await?;
+
+    // copy first (the temp file may live on another file system), then move into place by hash
+    let tmp = dir.join(format!(".upload-{:016x}", rand::random::<u64>()));
+    file.copy_to(&tmp).await?;
+    let contents = rocket::tokio::fs::read(&tmp).await?;
+    let mut hasher = Sha256::new();
+    hasher.input(&contents);
+    let sha256 = hasher.result_str();
+    rocket::tokio::fs::rename(&tmp, stored_path(config, &sha256)).await?;
+
+    Ok(StoredFile {
+        filename: original_name(file).unwrap_or_else(|| String::from("upload")),
+        content_type: file
+            .content_type()
+            .map(|ct| ct.to_string())
+            .unwrap_or_else(|| String::from("application/octet-stream")),
+        size: contents.len() as u64,
+        sha256: sha256,
+    })
+}
+
+#[get("/<num>/file/<qnum>/<user>")]
+pub(crate) async fn download(
+    _adm: Admin,
+    num: LectureId,
+    qnum: QuestionId,
+    user: String,
+    backend: &State<Arc<Mutex<MySqlBackend>>>,
+    config: &State<Config>,
+) -> Option<Download> {
+    let res = {
+        let mut bg = backend.lock().unwrap();
+        // while grading is blind, links name the student by pseudonym
+        let user = grading::resolve_student(&mut bg, config, num, &user)?;
+        bg.prep_exec(
+            "SELECT filename, content_type, sha256 FROM uploads WHERE lec = ? AND q = ? AND email = ?",
+            vec![num.into(), qnum.into(), user.into()],
+        )
+    };
+    let r = res.into_iter().next()?;
+    let filename: String = from_value(r[0].clone());
+    let content_type: String = from_value(r[1].clone());
+    let sha256: String = from_value(r[2].clone());
+
+    let file = NamedFile::open(stored_path(config, &sha256)).await.ok()?;
+    Some(Download {
+        file: file,
+        content_type: ContentType::parse_flexible(&content_type).unwrap_or(ContentType::Binary),
+        disposition: Header::new(
+            "Content-Disposition",
+            format!(
+                "attachment; filename=\"{}\"",
+                filename.replace(|c: char| c == '"' || c == '\\' || c.is_control(), "_")
+            ),
+        ),
+    })
+}